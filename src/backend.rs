@@ -0,0 +1,370 @@
+//! The git operations that git-run does most, on big repositories: checking status, building trees
+//! of the worktree, comparing trees, and staging.
+//!
+//! With the `git2` feature these happen in-process with libgit2, falling back to the `git` CLI for
+//! repositories libgit2 can't open, or which have filter drivers such as git-lfs,
+//! which libgit2 doesn't run.
+//! Commits always go through `git commit`, so that hooks, signing and the user's config apply.
+
+#[cfg(feature = "git2")]
+use crate::invocation::from_bytes;
+use crate::{errexit, git, prints_anything, read, run, snapshot};
+use std::{
+    collections::BTreeSet,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+pub trait Backend {
+    /// Whether the index and worktree match HEAD, with no untracked files.
+    fn is_clean(&self) -> anyhow::Result<bool>;
+    /// The tree at HEAD.
+    fn head_tree(&self) -> anyhow::Result<String>;
+    /// A tree holding the contents of the worktree, including untracked files,
+    /// without touching the index.
+    fn worktree_tree(&self) -> anyhow::Result<String>;
+    /// Paths which differ between trees `from` and `to`, relative to the top of the repository.
+    ///
+    /// If `pathspecs` isn't empty, only paths matching them.
+    /// If `added`, only paths which are in `to` but not in `from`.
+    fn diff_trees(
+        &self,
+        from: &str,
+        to: &str,
+        added: bool,
+        pathspecs: &[OsString],
+    ) -> anyhow::Result<BTreeSet<PathBuf>>;
+    /// Paths which differ between HEAD and the index.
+    fn staged(&self) -> anyhow::Result<BTreeSet<PathBuf>>;
+    /// Update the index entries for `paths` to match the worktree, like `git add --all`.
+    fn stage(&self, paths: &BTreeSet<PathBuf>) -> anyhow::Result<()>;
+}
+
+/// The backend for the repository whose worktree is at `root`.
+pub fn open(root: &Path) -> anyhow::Result<Box<dyn Backend>> {
+    #[cfg(feature = "git2")]
+    if let Ok(repository) = git2::Repository::open(root) {
+        if has_filters(&repository)? {
+            tracing::debug!("filter drivers are configured, so using the git CLI");
+            return Ok(Box::new(Cli { root: root.into() }));
+        }
+        return Ok(Box::new(Native {
+            repository,
+            cli: Cli { root: root.into() },
+        }));
+    }
+    Ok(Box::new(Cli { root: root.into() }))
+}
+
+/// Shells out to `git`.
+struct Cli {
+    root: PathBuf,
+}
+
+impl Cli {
+    fn git(&self) -> Command {
+        let mut git = git();
+        git.current_dir(&self.root);
+        git
+    }
+}
+
+impl Backend for Cli {
+    fn is_clean(&self) -> anyhow::Result<bool> {
+        Ok(!prints_anything(
+            self.git().args(["status", "--porcelain"]),
+        )?)
+    }
+
+    fn head_tree(&self) -> anyhow::Result<String> {
+        Ok(read(self.git().args(["rev-parse", "HEAD^{tree}"]))?
+            .trim()
+            .into())
+    }
+
+    fn worktree_tree(&self) -> anyhow::Result<String> {
+        let scratch = tempfile::tempdir()?;
+        let index = scratch.path().join("index");
+        // start from the real index so that unchanged files aren't rehashed
+        let real = self
+            .root
+            .join(read(self.git().args(["rev-parse", "--git-path", "index"]))?.trim());
+        if real.exists() {
+            fs::copy(real, &index)?;
+        }
+        errexit(run(self
+            .git()
+            .args(["add", "--all"])
+            .env("GIT_INDEX_FILE", &index)
+            .stdout(Stdio::null())
+            .stderr(Stdio::inherit()))?)?;
+        Ok(
+            read(self.git().arg("write-tree").env("GIT_INDEX_FILE", &index))?
+                .trim()
+                .into(),
+        )
+    }
+
+    fn diff_trees(
+        &self,
+        from: &str,
+        to: &str,
+        added: bool,
+        pathspecs: &[OsString],
+    ) -> anyhow::Result<BTreeSet<PathBuf>> {
+        snapshot::paths(
+            self.git()
+                .args(["diff-tree", "-r", "-z", "--name-only", "--no-renames"])
+                .args(added.then_some("--diff-filter=A"))
+                .args([from, to, "--"])
+                .args(pathspecs),
+        )
+    }
+
+    fn staged(&self) -> anyhow::Result<BTreeSet<PathBuf>> {
+        snapshot::paths(self.git().args([
+            "diff-index",
+            "--cached",
+            "-z",
+            "--name-only",
+            "--no-renames",
+            "HEAD",
+        ]))
+    }
+
+    fn stage(&self, paths: &BTreeSet<PathBuf>) -> anyhow::Result<()> {
+        let pathspecs = snapshot::pathspec_file(paths)?;
+        errexit(run(snapshot::git_paths(
+            &self.root,
+            &["add", "--all"],
+            &pathspecs,
+        )
+        .stdin(Stdio::null())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit()))?)?;
+        Ok(())
+    }
+}
+
+/// In-process, with libgit2.
+#[cfg(feature = "git2")]
+struct Native {
+    repository: git2::Repository,
+    /// For the pathspec magic libgit2 doesn't understand.
+    cli: Cli,
+}
+
+#[cfg(feature = "git2")]
+impl Native {
+    fn tree(&self, id: &str) -> anyhow::Result<git2::Tree<'_>> {
+        Ok(self.repository.find_tree(git2::Oid::from_str(id)?)?)
+    }
+}
+
+#[cfg(feature = "git2")]
+impl Backend for Native {
+    fn is_clean(&self) -> anyhow::Result<bool> {
+        let statuses = self.repository.statuses(Some(
+            git2::StatusOptions::new()
+                .include_untracked(true)
+                .include_ignored(false),
+        ))?;
+        Ok(statuses.is_empty())
+    }
+
+    fn head_tree(&self) -> anyhow::Result<String> {
+        Ok(self.repository.head()?.peel_to_tree()?.id().to_string())
+    }
+
+    fn worktree_tree(&self) -> anyhow::Result<String> {
+        // the repository's copy of the real index, which is never written back
+        let mut index = self.repository.index()?;
+        index.update_all(["*"], None)?;
+        index.add_all(["*"], git2::IndexAddOption::DEFAULT, None)?;
+        let tree = index.write_tree_to(&self.repository)?;
+        // throw away the changes, which the repository would otherwise keep hold of
+        index.read(true)?;
+        Ok(tree.to_string())
+    }
+
+    fn diff_trees(
+        &self,
+        from: &str,
+        to: &str,
+        added: bool,
+        pathspecs: &[OsString],
+    ) -> anyhow::Result<BTreeSet<PathBuf>> {
+        let mut include = vec![];
+        let mut exclude = vec![];
+        for pathspec in pathspecs {
+            let pathspec = pathspec.to_string_lossy();
+            match pathspec.strip_prefix(":(exclude)") {
+                Some(it) => exclude.push(it.to_owned()),
+                None if pathspec.starts_with(':') => {
+                    return self.cli.diff_trees(from, to, added, pathspecs)
+                }
+                None => include.push(pathspec.into_owned()),
+            }
+        }
+        let exclude = match exclude.is_empty() {
+            true => None,
+            false => Some(git2::Pathspec::new(&exclude)?),
+        };
+
+        let mut options = git2::DiffOptions::new();
+        for it in &include {
+            options.pathspec(it);
+        }
+        let diff = self.repository.diff_tree_to_tree(
+            Some(&self.tree(from)?),
+            Some(&self.tree(to)?),
+            Some(&mut options),
+        )?;
+        let paths = paths(
+            diff.deltas()
+                .filter(|it| !added || it.status() == git2::Delta::Added),
+        )?;
+        Ok(paths
+            .into_iter()
+            .filter(|path| match &exclude {
+                Some(exclude) => !exclude.matches_path(path, git2::PathspecFlags::DEFAULT),
+                None => true,
+            })
+            .collect())
+    }
+
+    fn staged(&self) -> anyhow::Result<BTreeSet<PathBuf>> {
+        let head = self.repository.head()?.peel_to_tree()?;
+        let diff = self
+            .repository
+            .diff_tree_to_index(Some(&head), None, None)?;
+        paths(diff.deltas())
+    }
+
+    fn stage(&self, paths: &BTreeSet<PathBuf>) -> anyhow::Result<()> {
+        let mut index = self.repository.index()?;
+        for path in paths {
+            match self.cli.root.join(path).symlink_metadata() {
+                Ok(_) => index.add_path(path)?,
+                Err(_) => index.remove_path(path)?,
+            }
+        }
+        index.write()?;
+        Ok(())
+    }
+}
+
+/// Whether any `filter.<driver>.clean` or `.process` command is configured,
+/// which `git add` would run but libgit2 wouldn't.
+#[cfg(feature = "git2")]
+fn has_filters(repository: &git2::Repository) -> anyhow::Result<bool> {
+    let config = repository.config()?;
+    let mut entries = config.entries(Some(r"^filter\..*\.(clean|process)$"))?;
+    Ok(entries.next().transpose()?.is_some())
+}
+
+#[cfg(feature = "git2")]
+fn paths<'a>(
+    deltas: impl Iterator<Item = git2::DiffDelta<'a>>,
+) -> anyhow::Result<BTreeSet<PathBuf>> {
+    deltas
+        .filter_map(|it| it.new_file().path_bytes().or(it.old_file().path_bytes()))
+        .map(|it| from_bytes(it.to_vec()).map(PathBuf::from))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Repository;
+
+    #[cfg(feature = "git2")]
+    fn native(repository: &Repository) -> Native {
+        Native {
+            repository: git2::Repository::open(repository.path()).unwrap(),
+            cli: cli(repository),
+        }
+    }
+
+    fn cli(repository: &Repository) -> Cli {
+        Cli {
+            root: repository.path().into(),
+        }
+    }
+
+    /// What `backend` sees changed in the worktree since HEAD.
+    fn changed(backend: &dyn Backend) -> BTreeSet<PathBuf> {
+        let head = backend.head_tree().unwrap();
+        let worktree = backend.worktree_tree().unwrap();
+        backend.diff_trees(&head, &worktree, false, &[]).unwrap()
+    }
+
+    #[test]
+    #[cfg(feature = "git2")]
+    fn backends_agree() {
+        let repository = Repository::new();
+        repository.write("kept", "kept\n");
+        repository.write("modified", "before\n");
+        repository.write("deleted", "deleted\n");
+        repository.commit("files");
+        repository.write("modified", "after\n");
+        repository.write("dir/added", "added\n");
+        fs::remove_file(repository.path().join("deleted")).unwrap();
+
+        let (native, cli) = (native(&repository), cli(&repository));
+        assert!(!native.is_clean().unwrap());
+        assert!(!cli.is_clean().unwrap());
+        assert_eq!(native.head_tree().unwrap(), cli.head_tree().unwrap());
+        assert_eq!(
+            native.worktree_tree().unwrap(),
+            cli.worktree_tree().unwrap()
+        );
+        let expected = ["deleted", "dir/added", "modified"]
+            .map(PathBuf::from)
+            .into();
+        assert_eq!(changed(&native), expected);
+        assert_eq!(changed(&cli), expected);
+
+        let exclude = [OsString::from(":(exclude)dir")];
+        let (head, worktree) = (cli.head_tree().unwrap(), cli.worktree_tree().unwrap());
+        assert_eq!(
+            native
+                .diff_trees(&head, &worktree, false, &exclude)
+                .unwrap(),
+            cli.diff_trees(&head, &worktree, false, &exclude).unwrap(),
+        );
+        assert_eq!(
+            native.diff_trees(&head, &worktree, true, &[]).unwrap(),
+            [PathBuf::from("dir/added")].into(),
+        );
+
+        native.stage(&expected).unwrap();
+        assert_eq!(native.staged().unwrap(), expected);
+        assert_eq!(cli.staged().unwrap(), expected);
+    }
+
+    #[test]
+    fn filters_use_the_cli() {
+        let repository = Repository::new();
+        repository.git(&["config", "filter.upper.clean", "tr a-z A-Z"]);
+        repository.write(".gitattributes", "*.f filter=upper\n");
+        repository.write("t.f", "text\n");
+        repository.commit("filtered");
+        assert_eq!(repository.git(&["show", "HEAD:t.f"]), "TEXT\n");
+
+        // rewritten as it was, which the filter makes the same as what's committed
+        repository.write("t.f", "text\n");
+        assert!(changed(&cli(&repository)).is_empty());
+        assert!(changed(&*open(repository.path()).unwrap()).is_empty());
+        assert!(open(repository.path()).unwrap().is_clean().unwrap());
+
+        repository.write("t.f", "other\n");
+        let expected = [PathBuf::from("t.f")].into();
+        let backend = open(repository.path()).unwrap();
+        assert_eq!(changed(&*backend), expected);
+        backend.stage(&expected).unwrap();
+        assert_eq!(repository.git(&["show", ":t.f"]), "OTHER\n");
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{
+    cli::{shell, step, unstarted, Args},
+    config::Config,
+    invocation::Invocation,
+    shell::Shell,
+};
+use anyhow::{bail, Context as _};
+use std::{
+    cmp::Ordering,
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+/// Run each step in `file` in turn, committing after each.
+pub fn main(args: &Args, config: &Config, file: &Path) -> anyhow::Result<()> {
+    let steps = read(file, shell(args, config)).map_err(|e| unstarted(args, None, e))?;
+
+    let mut failure = None;
+    let mut ran = 0;
+    for invocation in &steps {
+        if let Err(e) = step(args, config, invocation) {
+            failure = Some(e);
+            break;
+        }
+        ran += 1;
+    }
+
+    // stdout is for the reports
+    let mut summary: Box<dyn Write> = match args.json() {
+        true => Box::new(io::stderr()),
+        false => Box::new(io::stdout()),
+    };
+    writeln!(summary, "ran {ran} of {} step(s):", steps.len())?;
+    for (ix, invocation) in steps.iter().enumerate() {
+        let status = match ix.cmp(&ran) {
+            Ordering::Less => "committed",
+            Ordering::Equal => "failed",
+            Ordering::Greater => "not run",
+        };
+        writeln!(summary, "  {status:<9}  {}", invocation.subject())?;
+    }
+
+    match failure {
+        None => Ok(()),
+        Some(e) => bail!("step {} of {} failed: {e:#}", ran + 1, steps.len()),
+    }
+}
+
+/// The steps in `file`, or stdin if it's `-`, as scripts for `shell` if there is one.
+fn read(file: &Path, shell: Option<Shell>) -> anyhow::Result<Vec<Invocation>> {
+    let script = match file == Path::new("-") {
+        true => io::read_to_string(io::stdin()).context("couldn't read steps from stdin")?,
+        false => fs::read_to_string(file)
+            .with_context(|| format!("couldn't read steps from {}", file.display()))?,
+    };
+    parse(&script, shell).with_context(|| format!("couldn't parse steps from {}", file.display()))
+}
+
+/// One step for each line of `script` which isn't blank or a `#` comment.
+fn parse(script: &str, shell: Option<Shell>) -> anyhow::Result<Vec<Invocation>> {
+    script
+        .lines()
+        .enumerate()
+        .map(|(ix, line)| (ix + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(lineno, line)| match &shell {
+            Some(shell) => Ok(Invocation::Shell {
+                shell: shell.clone(),
+                script: line.into(),
+            }),
+            None => {
+                let argv = shell_words::split(line)
+                    .with_context(|| format!("couldn't parse line {lineno}"))?;
+                Ok(Invocation::Argv(argv.into_iter().map(Into::into).collect()))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = "
+# format first
+cargo fmt --all
+
+  # indented comments too
+sed -i 's/a b/c/' 'x y.txt'   
+\t
+echo '#not a comment'
+";
+
+    #[test]
+    fn splits_lines_into_arguments() {
+        let steps = parse(SCRIPT, None).unwrap();
+        let argvs = steps
+            .iter()
+            .map(|it| match it {
+                Invocation::Argv(argv) => argv.iter().map(|it| it.to_str().unwrap()).collect(),
+                Invocation::Shell { .. } => panic!("{it:?} is a script"),
+            })
+            .collect::<Vec<Vec<_>>>();
+        assert_eq!(
+            argvs,
+            [
+                vec!["cargo", "fmt", "--all"],
+                vec!["sed", "-i", "s/a b/c/", "x y.txt"],
+                vec!["echo", "#not a comment"],
+            ]
+        );
+    }
+
+    #[test]
+    fn runs_lines_as_scripts() {
+        let shell = Shell::new("sh");
+        let steps = parse(SCRIPT, Some(shell.clone())).unwrap();
+        let scripts = steps
+            .iter()
+            .map(|it| match it {
+                Invocation::Shell { shell: it, script } => {
+                    assert_eq!(*it, shell);
+                    script.to_str().unwrap()
+                }
+                Invocation::Argv(_) => panic!("{it:?} isn't a script"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            scripts,
+            [
+                "cargo fmt --all",
+                "sed -i 's/a b/c/' 'x y.txt'",
+                "echo '#not a comment'",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_the_line_which_can_not_be_split() {
+        let error = parse("true\n\necho 'unclosed\n", None).unwrap_err();
+        assert_eq!(error.to_string(), "couldn't parse line 3");
+        assert!(parse("# only comments\n\n", None).unwrap().is_empty());
+    }
+}
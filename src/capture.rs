@@ -0,0 +1,128 @@
+//! Running a command with its output shown as it is printed, maybe also keeping a copy of it.
+
+use crate::{get_program_and_args, signals::Forward};
+use anyhow::Context as _;
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    process::{Child, Command, ExitStatus, Output, Stdio},
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Like [`crate::run`], but the command reads from `stdin`, and its output goes straight to our
+/// own stdout and stderr rather than being collected.
+///
+/// Without a `transcript`, the command writes to our streams itself, so if they're a terminal it
+/// can tell, and shows colours and progress bars as usual.
+/// With one, both streams are passed through as they arrive and appended to `transcript`,
+/// interleaved, which means the command writes to pipes.
+/// If `stdout_to_stderr`, both streams go to our stderr.
+/// SIGINT and SIGTERM are passed on to the command as `signals` says.
+/// If it's still running after `timeout`, it is killed, and the last element of the result is `true`.
+///
+/// [`Output::stdout`] and [`Output::stderr`] are always empty.
+pub fn stream<'a>(
+    command: &'a mut Command,
+    stdin: Stdio,
+    stdout_to_stderr: bool,
+    transcript: Option<&File>,
+    signals: &Forward,
+    timeout: Option<Duration>,
+) -> anyhow::Result<(&'a mut Command, Output, bool)> {
+    let (program, args) = get_program_and_args(command);
+    tracing::debug!(?program, ?args, capture = transcript.is_some(), "running");
+    command.stdin(stdin);
+    match transcript {
+        Some(_) => command.stdout(Stdio::piped()).stderr(Stdio::piped()),
+        None if stdout_to_stderr => command.stdout(io::stderr()).stderr(Stdio::inherit()),
+        None => command.stdout(Stdio::inherit()).stderr(Stdio::inherit()),
+    };
+    signals.prepare(command);
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("couldn't run {program:?} with arguments {args:?}"))?;
+    signals.to(&child);
+    let deadline = timeout.map(|it| Instant::now() + it);
+
+    let (status, timed_out) = match transcript {
+        None => wait(&mut child, deadline, signals)?,
+        Some(transcript) => {
+            let transcript = Mutex::new(transcript);
+            let stdout = child.stdout.take();
+            let stderr = child.stderr.take();
+            thread::scope(|scope| {
+                let copiers = [
+                    stdout.map(|pipe| {
+                        scope.spawn(|| match stdout_to_stderr {
+                            true => copy(pipe, io::stderr(), &transcript),
+                            false => copy(pipe, io::stdout(), &transcript),
+                        })
+                    }),
+                    stderr.map(|pipe| scope.spawn(|| copy(pipe, io::stderr(), &transcript))),
+                ];
+                // the copying finishes once the command and anything it started have exited
+                let waited = wait(&mut child, deadline, signals);
+                for copier in copiers.into_iter().flatten() {
+                    copier
+                        .join()
+                        .expect("copying thread panicked")
+                        .context("couldn't copy output")?;
+                }
+                anyhow::Ok(waited?)
+            })?
+        }
+    };
+
+    Ok((
+        command,
+        Output {
+            status,
+            stdout: vec![],
+            stderr: vec![],
+        },
+        timed_out,
+    ))
+}
+
+/// Wait for `child` to exit, killing it at `deadline`, and say whether it was.
+fn wait(
+    child: &mut Child,
+    deadline: Option<Instant>,
+    signals: &Forward,
+) -> io::Result<(ExitStatus, bool)> {
+    let Some(deadline) = deadline else {
+        return Ok((child.wait()?, false));
+    };
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok((status, false));
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            tracing::info!(pid = child.id(), "timed out, killing");
+            signals.kill(child)?;
+            return Ok((child.wait()?, true));
+        }
+        thread::sleep((deadline - now).min(Duration::from_millis(50)));
+    }
+}
+
+fn copy(mut from: impl Read, mut to: impl Write, transcript: &Mutex<&File>) -> io::Result<()> {
+    let mut buf = [0; 8192];
+    loop {
+        let n = match from.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        to.write_all(&buf[..n])?;
+        to.flush()?;
+        transcript
+            .lock()
+            .expect("copying threads don't panic")
+            .write_all(&buf[..n])?;
+    }
+}
@@ -0,0 +1,539 @@
+//! The `git-run` command line.
+
+use crate::{
+    batch,
+    config::{self, Config},
+    errexit,
+    error::{ErrorKind, Failure},
+    git,
+    invocation::Invocation,
+    log, prefix, read, rebase, replay,
+    report::{Format, Report},
+    review, run,
+    runner::{shown, RunSpec, Runner},
+    shell::Shell,
+    signals,
+    template::{Placeholder, Template},
+    timeout::Timeout,
+};
+use anyhow::{anyhow, bail};
+use clap::{CommandFactory, Parser as _};
+use itertools::Itertools as _;
+use std::{ffi::OsString, path::PathBuf};
+use tracing::{debug, info_span};
+
+#[derive(Debug, clap::Parser)]
+#[command(
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    disable_help_subcommand = true
+)]
+pub struct Args {
+    #[command(subcommand)]
+    subcommand: Option<Subcommand>,
+    #[command(flatten)]
+    log: log::Args,
+    /// Run COMMAND as a script in SHELL.
+    ///
+    /// There must be only one argument.
+    /// SHELL defaults to the `shell` setting (see `git run config --show`), or bash.
+    /// Bash is invoked with `-O globstar`, PowerShell with `-Command`, and other shells with `-c`.
+    /// The shell is recorded in a `Run-Shell` trailer.
+    #[arg(
+        short,
+        long,
+        value_name = "SHELL",
+        num_args(0..=1),
+        require_equals = true
+    )]
+    shell: Option<Option<String>>,
+    /// Don't prompt for confirmation before committing.
+    #[arg(short, long, alias = "no-confirm", overrides_with = "confirm")]
+    yes: bool,
+    /// Prompt for confirmation before committing, even if the `confirm` setting is false.
+    #[arg(long, overrides_with = "yes")]
+    confirm: bool,
+    /// Review COMMAND's changes file by file, choosing what to commit and editing the message.
+    ///
+    /// Files may be left out, or committed hunk by hunk as with `git add --patch`.
+    /// Aborting the review rolls the changes back.
+    #[arg(long, conflicts_with_all = ["yes", "dry_run"])]
+    review: bool,
+    /// Show the changes COMMAND makes, then roll them back instead of committing.
+    #[arg(short = 'n', long, conflicts_with_all = ["no_rollback", "file"])]
+    dry_run: bool,
+    /// Leave COMMAND's changes in place if it fails or the commit is declined.
+    ///
+    /// By default, the paths COMMAND changed are restored to how they were before it ran.
+    #[arg(long, overrides_with = "rollback")]
+    no_rollback: bool,
+    /// Roll back COMMAND's changes if it fails, even if the `rollback` setting is false.
+    #[arg(long, overrides_with = "no_rollback")]
+    rollback: bool,
+    /// Allow dirty or untracked files, and commit only the paths that COMMAND changed.
+    ///
+    /// Fails if COMMAND changes a path which was already dirty.
+    #[arg(long)]
+    allow_dirty: bool,
+    /// Run COMMAND in a temporary worktree at HEAD, leaving this one untouched.
+    ///
+    /// Once committed, the current branch is fast-forwarded to the new commit.
+    #[arg(long, conflicts_with = "allow_dirty")]
+    worktree: bool,
+    /// With --worktree, create branch NAME for the new commit instead of fast-forwarding.
+    #[arg(long, value_name = "NAME", requires = "worktree")]
+    branch: Option<String>,
+    /// Only commit changed paths which match PATHSPEC.
+    ///
+    /// May be given multiple times.
+    /// Pathspecs are relative to the top of the repository.
+    /// Defaults to the `include` setting, or everything.
+    /// Changed paths which aren't committed are left in place, with a warning.
+    #[arg(long, value_name = "PATHSPEC")]
+    include: Vec<String>,
+    /// Don't commit changed paths which match PATHSPEC.
+    ///
+    /// May be given multiple times.
+    /// Pathspecs are relative to the top of the repository.
+    /// Defaults to the `exclude` setting.
+    #[arg(long, value_name = "PATHSPEC")]
+    exclude: Vec<String>,
+    /// The commit message, with placeholders for details of the run.
+    ///
+    /// The first line is the subject, and the rest is the body.
+    /// Placeholders are `{command}`, `{program}`, `{exit_code}`, `{duration}`, `{changed_files}` and `{cwd}`.
+    /// Write `{{` and `}}` for literal braces.
+    /// Defaults to the `message-template` setting, or `run: {command}`.
+    /// Trailers recording the command are always added.
+    #[arg(short, long, value_name = "TEMPLATE")]
+    message_template: Option<Template>,
+    /// Keep a copy of COMMAND's output, and attach it to the commit as a note.
+    ///
+    /// Output is still shown as it is printed.
+    /// The note is under `refs/notes/git-run`, so can be shown with `git log --notes=git-run`.
+    /// The exit code and duration are recorded in `Run-Exit-Code` and `Run-Duration` trailers.
+    #[arg(long)]
+    capture: bool,
+    /// Let COMMAND read from the terminal, for commands which prompt.
+    ///
+    /// Otherwise COMMAND's stdin is empty.
+    /// What is typed isn't recorded, so use --stdin for runs which should be replayable.
+    /// Can't be used with --capture, which would stop COMMAND's output going to the terminal.
+    #[arg(short, long, conflicts_with_all = ["stdin", "capture"])]
+    interactive: bool,
+    /// Feed the contents of FILE to COMMAND's stdin.
+    ///
+    /// The contents are stored in the repository, under `refs/git-run/stdin/`,
+    /// and recorded in a `Run-Stdin` trailer so that `git run replay` and `git run rebase`
+    /// give the command the same input.
+    #[arg(long, value_name = "FILE")]
+    stdin: Option<PathBuf>,
+    /// Set the environment variable KEY to VALUE for COMMAND.
+    ///
+    /// May be given multiple times.
+    /// Variables which are set, or kept with --env-keep, are recorded in trailers,
+    /// so that `git run replay` and `git run rebase` run COMMAND in the same environment.
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_env)]
+    env: Vec<(String, String)>,
+    /// Run COMMAND with an empty environment, apart from --env and --env-keep.
+    #[arg(long)]
+    env_clear: bool,
+    /// With --env-clear, keep the environment variables whose names match GLOB, like `PATH` or `CARGO_*`.
+    ///
+    /// May be given multiple times. Implies --env-clear.
+    #[arg(long, value_name = "GLOB")]
+    env_keep: Vec<String>,
+    /// Run COMMAND with `LC_ALL=C`, `TZ=UTC`, and `SOURCE_DATE_EPOCH` set to the time of HEAD.
+    ///
+    /// --env takes precedence.
+    #[arg(long)]
+    hermetic: bool,
+    /// How to report what happened.
+    ///
+    /// With `json`, a line of JSON is printed for each command, with its exit code, duration,
+    /// changed files, the new commit, and any error with a stable `kind`.
+    /// Everything else printed to stdout, including COMMAND's output, goes to stderr instead.
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t)]
+    output: Format,
+    /// Kill COMMAND if it runs for longer than DURATION, like `90s`, `5m` or `1h`.
+    ///
+    /// Its whole process group is killed, or with `--interactive`, which shares git-run's group,
+    /// only COMMAND itself. Its changes are rolled back as if it had failed.
+    /// Defaults to the `timeout` setting, or no limit. `0` or `none` means no limit.
+    #[arg(long, value_name = "DURATION")]
+    timeout: Option<Timeout>,
+    /// Commit COMMAND's changes even if it exits with a non-zero status.
+    #[arg(long, conflicts_with = "expect_exit")]
+    allow_failure: bool,
+    /// Commit COMMAND's changes only if it exits with one of CODES.
+    ///
+    /// Defaults to `0`.
+    /// A non-zero exit code is recorded in a `Run-Exit-Code` trailer.
+    #[arg(long, value_name = "CODES", value_delimiter = ',')]
+    expect_exit: Vec<i32>,
+    /// The command and its arguments.
+    ///
+    /// The commit message will be `run: [COMMAND]...`,
+    /// with the exact arguments recorded in a `Run-Argv` trailer.
+    ///
+    /// To run a program with the same name as a subcommand, precede it with `--`.
+    #[arg(num_args(1..), required_unless_present = "file")]
+    command: Vec<OsString>,
+    /// Run each line of FILE as a separate command, making a commit for each.
+    ///
+    /// Lines are split into arguments like a shell would, or with --shell, run as scripts.
+    /// Blank lines and lines starting with `#` are ignored.
+    /// Stops at the first command which fails.
+    /// If FILE is `-`, lines are read from stdin.
+    #[arg(short, long, value_name = "FILE", conflicts_with = "command")]
+    file: Option<PathBuf>,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Subcommand {
+    /// Re-run the commands recorded in `run:` commits, and check that they reproduce.
+    Replay(replay::Args),
+    /// Rebase, re-running the commands of `run:` commits instead of picking their changes.
+    Rebase(rebase::Args),
+    /// Re-run the command recorded in a `run:` commit on HEAD, and commit with the same message.
+    ///
+    /// This is what `git run rebase` uses in place of `pick`,
+    /// and may be used with `exec` in a manual `git rebase --interactive`.
+    Regenerate(rebase::RegenerateArgs),
+    /// Show settings from `.git-run.toml` and `git config run.*`.
+    ///
+    /// `allow` and `forbid` are checked against the program git-run starts,
+    /// and for --shell, the shell and the first word of the script.
+    /// --shell can't be used while `allow` is set.
+    /// They guard against mistakes rather than being a sandbox, since a program may run others,
+    /// as `env rm` does.
+    Config(config::Args),
+    #[command(hide = true)]
+    EditTodo(rebase::EditTodoArgs),
+}
+
+/// The `git-run` command line.
+pub fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    log::init(&args.log)?;
+
+    match args.subcommand {
+        Some(Subcommand::Replay(args)) => return replay::main(args),
+        Some(Subcommand::Rebase(args)) => return rebase::main(args),
+        Some(Subcommand::Regenerate(args)) => return rebase::regenerate(args),
+        Some(Subcommand::Config(args)) => return config::main(args),
+        Some(Subcommand::EditTodo(args)) => return rebase::edit_todo(args),
+        None => {}
+    }
+
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            let command =
+                (!args.command.is_empty()).then(|| Invocation::Argv(args.command.clone()));
+            return Err(unstarted(
+                &args,
+                command.as_ref(),
+                ErrorKind::Config.wrap(e),
+            ));
+        }
+    };
+
+    if let Some(file) = &args.file {
+        return batch::main(&args, &config, file);
+    }
+
+    let invocation = match (shell(&args, &config), args.command.as_slice()) {
+        (Some(shell), [script]) => Invocation::Shell {
+            shell,
+            script: script.clone(),
+        },
+        (Some(_), _) => Args::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "when --shell is supplied, COMMAND must be a single string",
+            )
+            .exit(),
+        (None, [_, ..]) => Invocation::Argv(args.command.clone()),
+        (None, _) => unreachable!("#[arg(num_args(1..)] prevents us getting here"),
+    };
+
+    step(&args, &config, &invocation)
+}
+
+impl Args {
+    /// Whether stdout is reserved for `--output json`.
+    pub fn json(&self) -> bool {
+        self.output == Format::Json
+    }
+}
+
+/// `KEY=VALUE`, for `--env`.
+fn parse_env(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.into(), value.into())),
+        _ => Err(format!("expected KEY=VALUE, not {s:?}")),
+    }
+}
+
+/// The shell that `--shell` asked for, if any.
+pub fn shell(args: &Args, config: &Config) -> Option<Shell> {
+    match &args.shell {
+        Some(Some(program)) => Some(Shell::new(program)),
+        Some(None) => Some(Shell::new(&config.shell.value)),
+        None => None,
+    }
+}
+
+/// Give up with `error` before running anything, still printing a report if one is expected.
+pub fn unstarted(
+    args: &Args,
+    invocation: Option<&Invocation>,
+    error: anyhow::Error,
+) -> anyhow::Error {
+    if args.json() {
+        if let Err(e) = Report::unstarted(invocation, &error).print() {
+            return error.context(format!("couldn't print the report: {e:#}"));
+        }
+    }
+    error
+}
+
+/// Run `invocation`, and commit its changes.
+pub fn step(args: &Args, config: &Config, invocation: &Invocation) -> anyhow::Result<()> {
+    let _span = info_span!("step", command = %invocation.command_line()).entered();
+    let mut report = Report::new(invocation);
+    let result = commit(args, config, invocation, &mut report);
+    if let Err(e) = &result {
+        if e.downcast_ref::<Failure>().is_some_and(|it| it.rolled_back) {
+            eprintln!("rolled back the changes made by the command");
+        }
+    }
+    if args.json() {
+        if let Err(e) = &result {
+            report.failed(e);
+        }
+        report.print()?;
+    }
+    result
+}
+
+fn commit(
+    args: &Args,
+    config: &Config,
+    invocation: &Invocation,
+    report: &mut Report,
+) -> anyhow::Result<()> {
+    config.permit(invocation)?;
+    let json = args.json();
+    if args.review && !args.dry_run && !review::can_prompt() {
+        bail!("--review needs a terminal to ask which changes to commit");
+    }
+
+    let include = match args.include.is_empty() {
+        true => &config.include.value,
+        false => &args.include,
+    };
+    let exclude = match args.exclude.is_empty() {
+        true => &config.exclude.value,
+        false => &args.exclude,
+    };
+    let rollback = match (args.rollback, args.no_rollback) {
+        (true, _) => true,
+        (_, true) => false,
+        _ => config.rollback.value,
+    };
+    let confirm = match (args.confirm, args.yes) {
+        (true, _) => true,
+        (_, true) => false,
+        _ => config.confirm.value,
+    };
+    let runner = Runner::new()
+        .allow_dirty(args.allow_dirty)
+        .worktree(args.worktree)
+        .branch(args.branch.clone())
+        .capture(args.capture)
+        .allow_failure(args.allow_failure)
+        .expect_exit(args.expect_exit.iter().copied())
+        .include(include)
+        .exclude(exclude)
+        .rollback(rollback)
+        .stdout_to_stderr(json)
+        .interactive(args.interactive)
+        .timeout(args.timeout.unwrap_or(config.timeout.value).duration())
+        // if we'll be asking anyway, ask whether to keep the changes of an interrupted command
+        .allow_signal((confirm || args.review) && !args.dry_run && review::can_prompt());
+    let mut spec = RunSpec::new(invocation.clone())
+        .cwd(prefix()?)
+        .env_clear(args.env_clear);
+    if let Some(path) = &args.stdin {
+        spec = spec.stdin(path);
+    }
+    if args.hermetic {
+        let epoch = read(git().args(["log", "-1", "--format=%ct", "HEAD"]))?;
+        spec = spec
+            .env("LC_ALL", "C")
+            .env("TZ", "UTC")
+            .env("SOURCE_DATE_EPOCH", epoch.trim());
+    }
+    for glob in &args.env_keep {
+        spec = spec.env_keep(glob);
+    }
+    for (key, value) in &args.env {
+        spec = spec.env(key, value);
+    }
+    let outcome = runner.run(&spec)?;
+    report.ran(&outcome)?;
+    if !outcome.left_out.is_empty() {
+        eprintln!(
+            "warning: not committing {} changed path(s) which aren't included by --include and --exclude: {}",
+            outcome.left_out.len(),
+            outcome.left_out.iter().map(|it| it.display()).join(", ")
+        );
+    }
+
+    let diff = outcome.diff();
+    let span = info_span!("status").entered();
+    match args.allow_dirty {
+        true => errexit(run(shown(
+            outcome
+                .git()
+                .args(["-c", "color.diff=always", "diff", "--stat", "--summary"])
+                .args(&diff),
+            json,
+        ))?)?,
+        false => errexit(run(shown(
+            outcome.git().args(["-c", "color.status=always", "status"]),
+            json,
+        ))?)?,
+    };
+    span.exit();
+
+    let cancelled = || ErrorKind::Cancelled.wrap(anyhow!("cancelled"));
+    if let Some(signal) = outcome.signal {
+        let give_up = match rollback {
+            true => "roll back",
+            false => "leave the changes uncommitted",
+        };
+        let choice = info_span!("confirm").in_scope(|| {
+            dialoguer::Select::new()
+                .with_prompt(format!(
+                    "the command was interrupted by {}",
+                    signals::name(signal)
+                ))
+                .items(&[give_up, "commit anyway"])
+                .default(0)
+                .interact_opt()
+        });
+        if !matches!(choice, Ok(Some(1))) {
+            return Err(outcome.abandon(cancelled()));
+        }
+    }
+
+    let mut message = outcome.message();
+    let template = match &args.message_template {
+        Some(template) => template,
+        None => &config.message_template.value,
+    };
+    (message.subject, message.body) = template.render(|placeholder| match placeholder {
+        Placeholder::Command => invocation.command_line(),
+        Placeholder::Program => invocation.program(),
+        Placeholder::ExitCode => outcome.exit_code.to_string(),
+        Placeholder::Duration => outcome.seconds(),
+        Placeholder::ChangedFiles => outcome.changed.iter().map(|it| it.display()).join(", "),
+        Placeholder::Cwd => outcome.cwd(),
+    });
+    for (key, value) in &config.trailers.value {
+        message = message.trailer(key, value);
+    }
+    let subject = &message.subject;
+
+    if args.dry_run {
+        errexit(run(shown(
+            outcome
+                .git()
+                .args(["diff", "--stat", "--patch"])
+                .args(&diff),
+            json,
+        ))?)?;
+        if outcome.worktree.is_none() {
+            outcome.rollback()?;
+            eprintln!("dry run, so rolled back the changes made by the command");
+        }
+        return Ok(());
+    }
+
+    if args.review {
+        let review = info_span!("review")
+            .in_scope(|| {
+                review::review(outcome.root(), &outcome.changed, message.to_string(), json)
+            })
+            .map_err(|e| outcome.abandon(e))?;
+        let commit = match review {
+            // only the chosen hunks are staged, so commit the index
+            Some(review) if review.partial => outcome.commit_index(&review.message)?,
+            Some(review) => outcome.commit_paths(&review.paths, &review.message)?,
+            None => return Err(outcome.abandon(cancelled())),
+        };
+        report.committed(commit);
+        return Ok(());
+    }
+
+    // choosing to commit anyway was confirmation enough
+    let permission = !confirm
+        || outcome.signal.is_some()
+        || info_span!("confirm").in_scope(|| {
+            dialoguer::Confirm::new()
+                .default(true)
+                .with_prompt(format!("commit with message `{subject}`"))
+                .interact()
+                .unwrap_or(false)
+        });
+    debug!(permission, "confirmation");
+
+    if !permission {
+        return Err(outcome.abandon(cancelled()));
+    }
+
+    report.committed(outcome.commit(&message.to_string())?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn env() {
+        assert_eq!(parse_env("TZ=UTC"), Ok(("TZ".into(), "UTC".into())));
+        assert_eq!(parse_env("FLAGS=-a=b"), Ok(("FLAGS".into(), "-a=b".into())));
+        assert_eq!(parse_env("EMPTY="), Ok(("EMPTY".into(), "".into())));
+        assert!(parse_env("TZ").is_err());
+        assert!(parse_env("=UTC").is_err());
+    }
+
+    fn parse(args: &[&str]) -> Args {
+        Args::try_parse_from([&"git-run"].into_iter().chain(args)).unwrap()
+    }
+
+    #[test]
+    fn expect_exit() {
+        let args = parse(&["--expect-exit", "1", "touch", "x"]);
+        assert_eq!(args.expect_exit, [1]);
+        assert_eq!(args.command, ["touch", "x"]);
+
+        let args = parse(&["--expect-exit", "0,1", "touch", "x"]);
+        assert_eq!(args.expect_exit, [0, 1]);
+        assert_eq!(args.command, ["touch", "x"]);
+
+        let args = parse(&["--expect-exit=2", "--expect-exit", "3", "--", "false"]);
+        assert_eq!(args.expect_exit, [2, 3]);
+        assert_eq!(args.command, ["false"]);
+    }
+
+    #[test]
+    fn definition() {
+        Args::command().debug_assert();
+    }
+}
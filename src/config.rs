@@ -0,0 +1,335 @@
+//! Settings from `.git-run.toml` at the top of the repository, and from `git config run.*`.
+//!
+//! Flags on the command line take precedence over git config,
+//! which takes precedence over `.git-run.toml`, which takes precedence over the defaults.
+
+use crate::{
+    errexit,
+    error::ErrorKind,
+    git,
+    invocation::Invocation,
+    run,
+    template::{self, Template},
+    timeout::Timeout,
+    toplevel,
+};
+use anyhow::{anyhow, bail, Context as _};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+pub const FILE_NAME: &str = ".git-run.toml";
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Print the effective settings, and where each came from.
+    #[arg(long, required = true)]
+    show: bool,
+}
+
+/// The contents of `.git-run.toml`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct File {
+    shell: Option<String>,
+    confirm: Option<bool>,
+    rollback: Option<bool>,
+    allow: Option<Vec<String>>,
+    forbid: Option<Vec<String>>,
+    trailers: Option<BTreeMap<String, String>>,
+    message_template: Option<String>,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    timeout: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    GitConfig(&'static str),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => f.write_str("default"),
+            Source::File(path) => write!(f, "from {}", path.display()),
+            Source::GitConfig(key) => write!(f, "from git config {key}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Setting<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+impl<T> Setting<T> {
+    fn default(value: T) -> Self {
+        Self {
+            value,
+            source: Source::Default,
+        }
+    }
+
+    fn set(&mut self, value: Option<T>, source: Source) {
+        if let Some(value) = value {
+            *self = Self { value, source }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The shell for a bare `--shell`.
+    pub shell: Setting<String>,
+    /// Whether to prompt before committing.
+    pub confirm: Setting<bool>,
+    /// Whether to roll back the command's changes if it fails or the commit is declined.
+    pub rollback: Setting<bool>,
+    /// If not empty, the only programs which may be run, and `--shell` may not be used.
+    ///
+    /// See [`Config::permit`] for what is checked.
+    pub allow: Setting<Vec<String>>,
+    /// Programs which may not be run, including shells for `--shell`.
+    pub forbid: Setting<Vec<String>>,
+    /// Extra trailers for every commit.
+    pub trailers: Setting<BTreeMap<String, String>>,
+    pub message_template: Setting<Template>,
+    /// If not empty, pathspecs for the changed paths which may be committed.
+    pub include: Setting<Vec<String>>,
+    /// Pathspecs for changed paths which shouldn't be committed.
+    pub exclude: Setting<Vec<String>>,
+    /// How long the command may run for before it's killed.
+    pub timeout: Setting<Timeout>,
+}
+
+impl Config {
+    pub fn load() -> anyhow::Result<Self> {
+        let mut config = Self {
+            shell: Setting::default(String::from("bash")),
+            confirm: Setting::default(true),
+            rollback: Setting::default(true),
+            allow: Setting::default(vec![]),
+            forbid: Setting::default(vec![]),
+            trailers: Setting::default(BTreeMap::new()),
+            message_template: Setting::default(
+                template::DEFAULT
+                    .parse()
+                    .expect("default template is valid"),
+            ),
+            include: Setting::default(vec![]),
+            exclude: Setting::default(vec![]),
+            timeout: Setting::default(Timeout::default()),
+        };
+
+        let path = toplevel(Path::new("."))?.join(FILE_NAME);
+        let file = match fs::read_to_string(&path) {
+            Ok(s) => toml::from_str::<File>(&s)
+                .with_context(|| format!("couldn't parse {}", path.display()))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => File::default(),
+            Err(e) => return Err(e).with_context(|| format!("couldn't read {}", path.display())),
+        };
+        let source = Source::File(path);
+        config.shell.set(file.shell, source.clone());
+        config.confirm.set(file.confirm, source.clone());
+        config.rollback.set(file.rollback, source.clone());
+        config.allow.set(file.allow, source.clone());
+        config.forbid.set(file.forbid, source.clone());
+        config.trailers.set(file.trailers, source.clone());
+        config.include.set(file.include, source.clone());
+        config.exclude.set(file.exclude, source.clone());
+        config.timeout.set(
+            file.timeout
+                .map(|it| it.parse())
+                .transpose()
+                .with_context(|| format!("invalid timeout {source}"))?,
+            source.clone(),
+        );
+        config.message_template.set(
+            file.message_template
+                .map(|it| it.parse())
+                .transpose()
+                .with_context(|| format!("invalid message-template {source}"))?,
+            source,
+        );
+
+        config
+            .shell
+            .set(get("run.shell")?.pop(), Source::GitConfig("run.shell"));
+        config
+            .confirm
+            .set(get_bool("run.confirm")?, Source::GitConfig("run.confirm"));
+        config
+            .rollback
+            .set(get_bool("run.rollback")?, Source::GitConfig("run.rollback"));
+        config.allow.set(
+            Some(get("run.allow")?).filter(|it| !it.is_empty()),
+            Source::GitConfig("run.allow"),
+        );
+        config.forbid.set(
+            Some(get("run.forbid")?).filter(|it| !it.is_empty()),
+            Source::GitConfig("run.forbid"),
+        );
+        let trailers = get("run.trailer")?
+            .into_iter()
+            .map(|it| match it.split_once(':') {
+                Some((key, value)) => Ok((key.trim().into(), value.trim().into())),
+                None => bail!("git config run.trailer {it:?} should be `Key: value`"),
+            })
+            .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+        config.trailers.set(
+            Some(trailers).filter(|it| !it.is_empty()),
+            Source::GitConfig("run.trailer"),
+        );
+        config.include.set(
+            Some(get("run.include")?).filter(|it| !it.is_empty()),
+            Source::GitConfig("run.include"),
+        );
+        config.exclude.set(
+            Some(get("run.exclude")?).filter(|it| !it.is_empty()),
+            Source::GitConfig("run.exclude"),
+        );
+        config.timeout.set(
+            get("run.timeout")?
+                .pop()
+                .map(|it| it.parse())
+                .transpose()
+                .context("invalid timeout from git config run.timeout")?,
+            Source::GitConfig("run.timeout"),
+        );
+        config.message_template.set(
+            get("run.messageTemplate")?
+                .pop()
+                .map(|it| it.parse())
+                .transpose()
+                .context("invalid message-template from git config run.messageTemplate")?,
+            Source::GitConfig("run.messageTemplate"),
+        );
+
+        Ok(config)
+    }
+
+    /// Check `invocation` against the `allow` and `forbid` lists.
+    ///
+    /// The program git-run starts is checked, which for `--shell` is the shell, as well as
+    /// the first word of the script.
+    /// A script may run anything, so `--shell` isn't allowed at all while `allow` is set.
+    /// A program may still run others, as `env rm` does, so this guards against mistakes
+    /// rather than being a sandbox.
+    pub fn permit(&self, invocation: &Invocation) -> anyhow::Result<()> {
+        let Self { allow, forbid, .. } = self;
+        let program = invocation.program();
+        if let Invocation::Shell { shell, .. } = invocation {
+            if !allow.value.is_empty() {
+                return Err(ErrorKind::Forbidden.wrap(anyhow!(
+                    "--shell scripts may run any command, so can't be used while there are allowed commands ({})",
+                    allow.source
+                )));
+            }
+            let shell = shell.name();
+            if forbid.value.contains(&shell) {
+                return Err(ErrorKind::Forbidden.wrap(anyhow!(
+                    "`{shell}` is a forbidden command ({})",
+                    forbid.source
+                )));
+            }
+        }
+        if !allow.value.is_empty() && !allow.value.contains(&program) {
+            return Err(ErrorKind::Forbidden.wrap(anyhow!(
+                "`{program}` is not in the allowed commands ({})",
+                allow.source
+            )));
+        }
+        if forbid.value.contains(&program) {
+            return Err(ErrorKind::Forbidden.wrap(anyhow!(
+                "`{program}` is a forbidden command ({})",
+                forbid.source
+            )));
+        }
+        Ok(())
+    }
+}
+
+pub fn main(_: Args) -> anyhow::Result<()> {
+    let Config {
+        shell,
+        confirm,
+        rollback,
+        allow,
+        forbid,
+        trailers,
+        message_template,
+        include,
+        exclude,
+        timeout,
+    } = Config::load()?;
+    show("shell", shell)?;
+    show("confirm", confirm)?;
+    show("rollback", rollback)?;
+    show("allow", allow)?;
+    show("forbid", forbid)?;
+    println!("# allow and forbid only check the program git-run starts, which may run others");
+    show("trailers", trailers)?;
+    show("include", include)?;
+    show("exclude", exclude)?;
+    show(
+        "timeout",
+        Setting {
+            value: timeout.value.to_string(),
+            source: timeout.source,
+        },
+    )?;
+    show(
+        "message-template",
+        Setting {
+            value: message_template.value.to_string(),
+            source: message_template.source,
+        },
+    )?;
+    Ok(())
+}
+
+fn show<T: Serialize>(key: &str, setting: Setting<T>) -> anyhow::Result<()> {
+    let value = toml::Value::try_from(setting.value)?;
+    println!("{key} = {value} # {}", setting.source);
+    Ok(())
+}
+
+/// All the values of git config `key`.
+fn get(key: &str) -> anyhow::Result<Vec<String>> {
+    get_all(key, &[])
+}
+
+fn get_bool(key: &str) -> anyhow::Result<Option<bool>> {
+    match get_all(key, &["--type=bool"])?.pop().as_deref() {
+        None => Ok(None),
+        Some("true") => Ok(Some(true)),
+        Some(_) => Ok(Some(false)),
+    }
+}
+
+fn get_all(key: &str, options: &[&str]) -> anyhow::Result<Vec<String>> {
+    let mut git = git();
+    let output = run(git
+        .arg("config")
+        .args(options)
+        .args(["--get-all", key])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit()))?;
+    match output.1.status.code() {
+        Some(1) => Ok(vec![]),
+        _ => Ok(String::from_utf8(errexit(output)?.stdout)
+            .with_context(|| format!("git config {key} is not valid UTF-8"))?
+            .lines()
+            .map(String::from)
+            .collect()),
+    }
+}
@@ -0,0 +1,157 @@
+//! The environment a command runs in, which is ours unless it's asked to be otherwise.
+//!
+//! Anything else is recorded, so that the command can be replayed in the same environment:
+//! variables which were set in a `Run-Env` trailer, as a JSON array of `KEY=VALUE` strings,
+//! and if the environment was cleared, the globs for the variables which were kept
+//! in a `Run-Env-Keep` trailer, as a JSON array.
+
+use crate::{
+    invocation::{decode, encode, from_bytes, to_bytes, RUN_ENV, RUN_ENV_KEEP},
+    message::{self, Message},
+};
+use anyhow::{bail, Context as _};
+use std::{env, ffi::OsString, process::Command};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Environment {
+    /// Whether to start from an empty environment, rather than ours.
+    pub clear: bool,
+    /// When clearing, globs for the names of our variables to keep anyway, like `PATH` or `CARGO_*`.
+    pub keep: Vec<String>,
+    /// Variables to set.
+    pub set: Vec<(OsString, OsString)>,
+}
+
+impl Environment {
+    /// Set `key` to `value`, instead of any value given before.
+    pub fn set(&mut self, key: OsString, value: OsString) {
+        self.set.retain(|(it, _)| *it != key);
+        self.set.push((key, value));
+    }
+
+    /// Give `command` this environment.
+    pub fn apply(&self, command: &mut Command) {
+        if self.clear {
+            command.env_clear();
+            command.envs(env::vars_os().filter(|(key, _)| {
+                let key = key.to_string_lossy();
+                self.keep
+                    .iter()
+                    .any(|glob| matches(glob.as_bytes(), key.as_bytes()))
+            }));
+        }
+        command.envs(self.set.iter().map(|(key, value)| (key, value)));
+    }
+
+    /// Add trailers to `message` recording how this differs from our environment.
+    pub fn record(&self, mut message: Message) -> Message {
+        if self.clear {
+            message = message.trailer(RUN_ENV_KEEP, encode(&self.keep));
+        }
+        if !self.set.is_empty() {
+            let set = self
+                .set
+                .iter()
+                .map(|(key, value)| {
+                    let mut variable = key.clone();
+                    variable.push("=");
+                    variable.push(value);
+                    variable
+                })
+                .collect::<Vec<_>>();
+            message = message.trailer(RUN_ENV, encode(&set));
+        }
+        message
+    }
+
+    /// The environment recorded in `message` by [`Self::record`].
+    pub fn recorded(message: &str) -> anyhow::Result<Self> {
+        let mut environment = Self::default();
+        if let Some(keep) = message::trailer(message, RUN_ENV_KEEP) {
+            environment.clear = true;
+            environment.keep = decode(keep)
+                .with_context(|| format!("malformed {RUN_ENV_KEEP} trailer {keep:?}"))?
+                .into_iter()
+                .map(|it| it.to_string_lossy().into_owned())
+                .collect();
+        }
+        if let Some(set) = message::trailer(message, RUN_ENV) {
+            for variable in
+                decode(set).with_context(|| format!("malformed {RUN_ENV} trailer {set:?}"))?
+            {
+                let mut key = to_bytes(&variable);
+                let Some(split) = key.iter().position(|it| *it == b'=') else {
+                    bail!("malformed {RUN_ENV} trailer {set:?}, expected `KEY=VALUE`s");
+                };
+                let value = key.split_off(split + 1);
+                key.pop();
+                environment.set(from_bytes(key)?, from_bytes(value)?);
+            }
+        }
+        Ok(environment)
+    }
+}
+
+/// Whether `name` matches `glob`, in which `*` matches any run of characters and `?` any one.
+fn matches(glob: &[u8], name: &[u8]) -> bool {
+    match (glob.split_first(), name.split_first()) {
+        (None, _) => name.is_empty(),
+        (Some((b'*', rest)), _) => {
+            matches(rest, name) || (!name.is_empty() && matches(glob, &name[1..]))
+        }
+        (Some((b'?', rest)), Some((_, name))) => matches(rest, name),
+        (Some((it, rest)), Some((other, name))) if it == other => matches(rest, name),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        assert!(matches(b"PATH", b"PATH"));
+        assert!(!matches(b"PATH", b"PATHS"));
+        assert!(matches(b"CARGO_*", b"CARGO_HOME"));
+        assert!(matches(b"CARGO_*", b"CARGO_"));
+        assert!(!matches(b"CARGO_*", b"RUSTUP_HOME"));
+        assert!(matches(b"*_HOME", b"CARGO_HOME"));
+        assert!(matches(b"*", b""));
+        assert!(matches(b"LC_?", b"LC_X"));
+        assert!(!matches(b"LC_?", b"LC_"));
+        assert!(!matches(b"LC_?", b"LC_ALL"));
+        assert!(matches(b"?*_?", b"GIT_A"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut environment = Environment {
+            clear: true,
+            keep: vec!["PATH".into(), "CARGO_*".into()],
+            set: vec![],
+        };
+        environment.set("TZ".into(), "UTC".into());
+        environment.set("EQUATION".into(), "a=b=c".into());
+        environment.set("EMPTY".into(), "".into());
+        #[cfg(unix)]
+        environment.set(
+            "BYTES".into(),
+            std::os::unix::ffi::OsStringExt::from_vec(vec![0xff, b'=', 0xfe]),
+        );
+        let message = environment.record(Message::new("subject")).to_string();
+        assert_eq!(Environment::recorded(&message).unwrap(), environment);
+    }
+
+    #[test]
+    fn nothing_recorded() {
+        let message = Environment::default()
+            .record(Message::new("subject"))
+            .to_string();
+        assert_eq!(message, "subject");
+        assert_eq!(
+            Environment::recorded(&message).unwrap(),
+            Environment::default()
+        );
+    }
+}
@@ -0,0 +1,160 @@
+//! Kinds of failure that callers may want to tell apart, which are stable across releases.
+
+use serde::Serialize;
+use std::{error::Error, fmt, time::Duration};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorKind {
+    /// The settings in `.git-run.toml` or `git config run.*` are invalid.
+    Config,
+    /// There were dirty or untracked files before running the command.
+    Dirty,
+    /// The command is forbidden by the `allow` or `forbid` settings.
+    Forbidden,
+    /// The command couldn't be started.
+    Spawn,
+    /// The command exited with an unexpected status.
+    CommandFailed,
+    /// The command was killed by a signal, or git-run was interrupted while it ran.
+    Signalled,
+    /// The command ran for longer than its timeout, so was killed.
+    TimedOut,
+    /// The command didn't change any files.
+    NoChanges,
+    /// The command changed paths which were already dirty.
+    Clobbered,
+    /// None of the changed paths are included by `--include` and `--exclude`.
+    NothingIncluded,
+    /// The user declined to commit.
+    Cancelled,
+    /// Anything else, such as git failing.
+    Other,
+}
+
+impl ErrorKind {
+    /// `error`, as a [`Failure`] of this kind.
+    pub fn wrap(self, error: anyhow::Error) -> anyhow::Error {
+        anyhow::Error::new(Failure {
+            kind: self,
+            exit_code: None,
+            signal: None,
+            duration: None,
+            rolled_back: false,
+            error,
+        })
+    }
+
+    /// The kind of `error`, [`ErrorKind::Other`] if it isn't a [`Failure`].
+    pub fn of(error: &anyhow::Error) -> Self {
+        match error.downcast_ref::<Failure>() {
+            Some(failure) => failure.kind,
+            None => ErrorKind::Other,
+        }
+    }
+}
+
+/// An error of a particular [`ErrorKind`], which displays as the error it wraps.
+#[derive(Debug)]
+pub struct Failure {
+    pub kind: ErrorKind,
+    /// The command's exit code, if it ran and exited.
+    pub exit_code: Option<i32>,
+    /// The signal, for [`ErrorKind::Signalled`].
+    pub signal: Option<i32>,
+    /// How long the command took, if it ran.
+    pub duration: Option<Duration>,
+    /// Whether the command's changes were rolled back.
+    pub rolled_back: bool,
+    error: anyhow::Error,
+}
+
+impl Failure {
+    pub(crate) fn signalled(signal: i32, error: anyhow::Error) -> anyhow::Error {
+        anyhow::Error::new(Self {
+            kind: ErrorKind::Signalled,
+            exit_code: None,
+            signal: Some(signal),
+            duration: None,
+            rolled_back: false,
+            error,
+        })
+    }
+
+    /// `error`, noting how the command which it came after went, if it's a [`Failure`].
+    pub(crate) fn ran(
+        mut error: anyhow::Error,
+        exit_code: Option<i32>,
+        duration: Duration,
+    ) -> anyhow::Error {
+        if let Some(failure) = error.downcast_mut::<Self>() {
+            failure.exit_code = exit_code;
+            failure.duration = Some(duration);
+        }
+        error
+    }
+
+    /// `error`, noting that the command's changes were rolled back.
+    pub(crate) fn rolled_back(mut error: anyhow::Error) -> anyhow::Error {
+        match error.downcast_mut::<Self>() {
+            Some(failure) => failure.rolled_back = true,
+            None => {
+                error = anyhow::Error::new(Self {
+                    kind: ErrorKind::Other,
+                    exit_code: None,
+                    signal: None,
+                    duration: None,
+                    rolled_back: true,
+                    error,
+                })
+            }
+        }
+        error
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl Error for Failure {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.error.source()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kinds_are_stable() {
+        let kinds = [
+            (ErrorKind::Config, "config"),
+            (ErrorKind::Dirty, "dirty"),
+            (ErrorKind::Forbidden, "forbidden"),
+            (ErrorKind::Spawn, "spawn"),
+            (ErrorKind::CommandFailed, "command-failed"),
+            (ErrorKind::Signalled, "signalled"),
+            (ErrorKind::TimedOut, "timed-out"),
+            (ErrorKind::NoChanges, "no-changes"),
+            (ErrorKind::Clobbered, "clobbered"),
+            (ErrorKind::NothingIncluded, "nothing-included"),
+            (ErrorKind::Cancelled, "cancelled"),
+            (ErrorKind::Other, "other"),
+        ];
+        for (kind, name) in kinds {
+            assert_eq!(serde_json::to_value(kind).unwrap(), name);
+        }
+    }
+
+    #[test]
+    fn of() {
+        let error = ErrorKind::NoChanges.wrap(anyhow::anyhow!("no changes"));
+        assert_eq!(ErrorKind::of(&error), ErrorKind::NoChanges);
+        assert_eq!(error.to_string(), "no changes");
+        assert_eq!(ErrorKind::of(&anyhow::anyhow!("other")), ErrorKind::Other);
+    }
+}
@@ -0,0 +1,530 @@
+//! What git-run was asked to run, and how that is recorded in a commit message.
+//!
+//! By default, the subject is `run: ` followed by the command, shell-quoted so that it may be pasted into a terminal.
+//! The exact arguments are recorded in a `Run-Argv` trailer, as a JSON array.
+//! Each element is a string, or an array of bytes for arguments which aren't valid UTF-8.
+//! `--shell` is recorded as a `Run-Shell` trailer naming the shell,
+//! with the script as the only element of `Run-Argv`.
+//! Commands run from a subdirectory record it in a `Run-Cwd` trailer, relative to the top of the repository.
+//! Input given with `--stdin` is stored as a blob, which is named in a `Run-Stdin` trailer.
+//! Changes committed despite the command being interrupted record the signal in a `Run-Signal` trailer.
+//! `--include` and `--exclude` pathspecs are recorded in `Run-Include` and `Run-Exclude` trailers,
+//! as JSON arrays like `Run-Argv`.
+//! A different environment is recorded in `Run-Env` and `Run-Env-Keep` trailers, see [`crate::environment`].
+
+use crate::{
+    environment::Environment,
+    errexit, expect_exit, git,
+    message::{self, Message},
+    read, run,
+    shell::Shell,
+    snapshot, visible,
+};
+use anyhow::{bail, Context as _};
+use serde_json::Value;
+use std::{
+    ffi::{OsStr, OsString},
+    fs::{self, File},
+    io::{Seek as _, Write as _},
+    path::{Component, Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+const PREFIX: &str = "run: ";
+/// Characters which mean a shell word isn't just the name of a command.
+const OPERATORS: &[char] = &[';', '&', '|', '<', '>', '(', ')', '{', '}', '$', '`', '='];
+pub const RUN_ARGV: &str = "Run-Argv";
+pub const RUN_SHELL: &str = "Run-Shell";
+pub const RUN_EXIT_CODE: &str = "Run-Exit-Code";
+pub const RUN_DURATION: &str = "Run-Duration";
+pub const RUN_CWD: &str = "Run-Cwd";
+pub const RUN_STDIN: &str = "Run-Stdin";
+pub const RUN_SIGNAL: &str = "Run-Signal";
+pub const RUN_ENV: &str = "Run-Env";
+pub const RUN_ENV_KEEP: &str = "Run-Env-Keep";
+pub const RUN_INCLUDE: &str = "Run-Include";
+pub const RUN_EXCLUDE: &str = "Run-Exclude";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Invocation {
+    /// `git run --shell[=SHELL] SCRIPT`
+    Shell { shell: Shell, script: OsString },
+    /// `git run PROGRAM [ARGS]...`
+    Argv(Vec<OsString>),
+}
+
+impl Invocation {
+    pub fn command(&self) -> Command {
+        match self {
+            Invocation::Shell { shell, script } => shell.command(script),
+            Invocation::Argv(argv) => {
+                let (first, rest) = argv
+                    .split_first()
+                    .expect("an Invocation::Argv is never empty");
+                let mut command = Command::new(first);
+                command.args(rest);
+                command
+            }
+        }
+    }
+
+    /// The name of the program being run, for shell scripts the first command of the script.
+    ///
+    /// If the script doesn't start with a plain command, like `(cd src && make)` or `X=1 make`,
+    /// this is the name of the shell.
+    pub fn program(&self) -> String {
+        let first = match self {
+            Invocation::Shell { shell, script } => {
+                let words = shell_words::split(&script.to_string_lossy()).unwrap_or_default();
+                match words
+                    .first()
+                    .map(|it| it.trim_end_matches([';', '&', '|']))
+                    .filter(|it| !it.is_empty() && !it.contains(OPERATORS))
+                {
+                    Some(command) => OsString::from(command),
+                    None => OsString::from(shell.name()),
+                }
+            }
+            Invocation::Argv(argv) => argv[0].clone(),
+        };
+        Path::new(&first)
+            .file_name()
+            .unwrap_or(&first)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// A human-readable summary, which isn't guaranteed to round-trip.
+    pub fn subject(&self) -> String {
+        format!("{PREFIX}{}", self.command_line())
+    }
+
+    /// The command as it would be typed into a shell, which isn't guaranteed to round-trip.
+    pub fn command_line(&self) -> String {
+        match self {
+            Invocation::Shell { script, .. } => script.to_string_lossy().into_owned(),
+            Invocation::Argv(argv) => shell_words::join(argv.iter().map(|it| it.to_string_lossy())),
+        }
+    }
+
+    pub fn message(&self) -> Message {
+        let message = Message::new(self.subject());
+        match self {
+            Invocation::Shell { shell, script } => message
+                .trailer(RUN_SHELL, shell.program())
+                .trailer(RUN_ARGV, encode([script])),
+            Invocation::Argv(argv) => message.trailer(RUN_ARGV, encode(argv)),
+        }
+    }
+
+    /// Recover an [`Invocation`] from a commit message written by [`Self::message`].
+    ///
+    /// Messages written before the `Run-Argv` trailer was introduced don't record
+    /// whether `--shell` was used, so the caller must say how to interpret them.
+    /// Back then, the shell was always bash.
+    pub fn parse(message: &str, shell: bool) -> anyhow::Result<Option<Self>> {
+        if let Some(argv) = message::trailer(message, RUN_ARGV) {
+            let mut argv =
+                decode(argv).with_context(|| format!("malformed {RUN_ARGV} trailer {argv:?}"))?;
+            return match (message::trailer(message, RUN_SHELL), argv.len()) {
+                (Some(shell), 1) => Ok(Some(Invocation::Shell {
+                    shell: Shell::new(shell),
+                    script: argv.remove(0),
+                })),
+                (Some(_), n) => {
+                    bail!("{RUN_SHELL} requires a single script in {RUN_ARGV}, not {n}")
+                }
+                (None, 0) => bail!("empty {RUN_ARGV} trailer"),
+                (None, _) => Ok(Some(Invocation::Argv(argv))),
+            };
+        }
+
+        let Some(command) = message.trim_end().strip_prefix(PREFIX) else {
+            return Ok(None);
+        };
+        match shell {
+            true => Ok(Some(Invocation::Shell {
+                shell: Shell::bash(),
+                script: command.into(),
+            })),
+            false => {
+                let argv = command
+                    .split_whitespace()
+                    .map(OsString::from)
+                    .collect::<Vec<_>>();
+                match argv.is_empty() {
+                    true => Ok(None),
+                    false => Ok(Some(Invocation::Argv(argv))),
+                }
+            }
+        }
+    }
+}
+
+/// A `run:` commit.
+pub struct Recorded {
+    pub invocation: Invocation,
+    pub message: String,
+}
+
+impl Recorded {
+    /// The exit code the command was accepted with.
+    pub fn exit_code(&self) -> anyhow::Result<i32> {
+        match message::trailer(&self.message, RUN_EXIT_CODE) {
+            Some(code) => code
+                .parse()
+                .with_context(|| format!("malformed {RUN_EXIT_CODE} trailer {code:?}")),
+            None => Ok(0),
+        }
+    }
+
+    /// The directory the command was run from, relative to the top of the repository.
+    pub fn cwd(&self) -> anyhow::Result<PathBuf> {
+        let Some(cwd) = message::trailer(&self.message, RUN_CWD) else {
+            return Ok(PathBuf::new());
+        };
+        let path = PathBuf::from(cwd);
+        match path.components().all(|it| matches!(it, Component::Normal(_))) {
+            true => Ok(path),
+            false => bail!("malformed {RUN_CWD} trailer {cwd:?}, expected a relative path inside the repository"),
+        }
+    }
+
+    /// Pathspecs selecting the changed paths which were committed, relative to the top of the repository.
+    pub fn pathspecs(&self) -> anyhow::Result<Vec<OsString>> {
+        let list = |key| match message::trailer(&self.message, key) {
+            Some(value) => decode(value)
+                .with_context(|| format!("malformed {key} trailer {value:?}"))
+                .map(|it| {
+                    it.into_iter()
+                        .map(|it| it.to_string_lossy().into_owned())
+                        .collect::<Vec<_>>()
+                }),
+            None => Ok(vec![]),
+        };
+        Ok(snapshot::pathspecs(
+            &list(RUN_INCLUDE)?,
+            &list(RUN_EXCLUDE)?,
+        ))
+    }
+
+    /// The command, to be run in the worktree at `root`, in the recorded directory and environment.
+    pub fn command(&self, root: &Path) -> anyhow::Result<Command> {
+        let mut command = self.invocation.command();
+        command.current_dir(self.cwd_in(root)?);
+        Environment::recorded(&self.message)?.apply(&mut command);
+        Ok(command)
+    }
+
+    /// Run the command again in the worktree at `root`, checking that it exits as it did,
+    /// and stage its changes to the paths which were committed, leaving out the rest as
+    /// `--include` and `--exclude` did.
+    pub fn rerun(&self, root: &Path) -> anyhow::Result<()> {
+        let exit_code = self.exit_code()?;
+        expect_exit(
+            run(visible(&mut self.command(root)?).stdin(self.stdin()?))?,
+            |it| it == exit_code,
+        )?;
+        errexit(run(visible(
+            git()
+                .current_dir(root)
+                .args(["add", "--all", "--"])
+                .args(self.pathspecs()?),
+        ))?)?;
+        Ok(())
+    }
+
+    /// [`Self::cwd`] in the worktree at `root`, which is created if need be,
+    /// since git doesn't record empty directories.
+    pub fn cwd_in(&self, root: &Path) -> anyhow::Result<PathBuf> {
+        let cwd = root.join(self.cwd()?);
+        fs::create_dir_all(&cwd).with_context(|| format!("couldn't create {}", cwd.display()))?;
+        Ok(cwd)
+    }
+
+    /// The input the command was given, which is nothing unless it was recorded.
+    pub fn stdin(&self) -> anyhow::Result<Stdio> {
+        let Some(blob) = message::trailer(&self.message, RUN_STDIN) else {
+            return Ok(Stdio::null());
+        };
+        if blob.is_empty() || !blob.bytes().all(|it| it.is_ascii_hexdigit()) {
+            bail!("malformed {RUN_STDIN} trailer {blob:?}, expected a blob id");
+        }
+        let contents = errexit(run(git()
+            .args(["cat-file", "blob", blob])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit()))?)
+        .with_context(|| format!("couldn't read the input recorded in {RUN_STDIN} {blob}"))?
+        .stdout;
+        let mut file = tempfile::tempfile()?;
+        file.write_all(&contents)?;
+        file.rewind()?;
+        Ok(file.into())
+    }
+}
+
+/// Store the contents of `path` as a blob in the repository at `root`, returning its id.
+pub fn store_stdin(root: &Path, path: &Path) -> anyhow::Result<String> {
+    let file = File::open(path).with_context(|| format!("couldn't open {}", path.display()))?;
+    let output = errexit(run(git()
+        .current_dir(root)
+        .args(["hash-object", "-w", "--stdin"])
+        .stdin(file)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit()))?)?;
+    Ok(String::from_utf8(output.stdout)
+        .context("git hash-object printed invalid UTF-8")?
+        .trim()
+        .into())
+}
+
+/// The [`Invocation`] recorded in `commit`'s message, if it is a `run:` commit.
+pub fn recorded(root: &Path, commit: &str, shell: bool) -> anyhow::Result<Option<Recorded>> {
+    let message = read(
+        git()
+            .current_dir(root)
+            .args(["log", "-1", "--format=%B", commit]),
+    )?;
+    Ok(Invocation::parse(&message, shell)
+        .with_context(|| format!("in the message of {commit}"))?
+        .map(|invocation| Recorded {
+            invocation,
+            message,
+        }))
+}
+
+pub fn encode<T: AsRef<OsStr>>(args: impl IntoIterator<Item = T>) -> String {
+    Value::Array(
+        args.into_iter()
+            .map(|arg| match arg.as_ref().to_str() {
+                Some(s) => Value::String(s.into()),
+                None => Value::Array(
+                    to_bytes(arg.as_ref())
+                        .into_iter()
+                        .map(Value::from)
+                        .collect(),
+                ),
+            })
+            .collect(),
+    )
+    .to_string()
+}
+
+pub fn decode(json: &str) -> anyhow::Result<Vec<OsString>> {
+    let Value::Array(args) = serde_json::from_str(json)? else {
+        bail!("expected an array")
+    };
+    args.into_iter()
+        .map(|arg| match arg {
+            Value::String(s) => Ok(s.into()),
+            Value::Array(bytes) => bytes
+                .into_iter()
+                .map(|byte| {
+                    byte.as_u64()
+                        .and_then(|it| u8::try_from(it).ok())
+                        .context("expected a byte")
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .and_then(from_bytes),
+            _ => bail!("expected a string or an array of bytes"),
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+pub fn to_bytes(s: &OsStr) -> Vec<u8> {
+    std::os::unix::ffi::OsStrExt::as_bytes(s).to_vec()
+}
+
+#[cfg(unix)]
+pub fn from_bytes(bytes: Vec<u8>) -> anyhow::Result<OsString> {
+    Ok(std::os::unix::ffi::OsStringExt::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+pub fn to_bytes(s: &OsStr) -> Vec<u8> {
+    s.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(not(unix))]
+pub fn from_bytes(bytes: Vec<u8>) -> anyhow::Result<OsString> {
+    Ok(String::from_utf8(bytes)?.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn encode_decode_round_trips() {
+        let args = argv(&["echo", "two words", "\"quoted\"", "it's", "", "new\nline"]);
+        assert_eq!(decode(&encode(&args)).unwrap(), args);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn encode_decode_round_trips_invalid_utf8() {
+        let args = vec![
+            OsString::from("touch"),
+            from_bytes(b"caf\xe9".to_vec()).unwrap(),
+        ];
+        let json = encode(&args);
+        assert_eq!(json, r#"["touch",[99,97,102,233]]"#);
+        assert_eq!(decode(&json).unwrap(), args);
+    }
+
+    #[test]
+    fn decode_rejects_malformed() {
+        assert!(decode(r#"{"a": 1}"#).is_err());
+        assert!(decode("[1]").is_err());
+        assert!(decode("[[256]]").is_err());
+    }
+
+    #[test]
+    fn parse_round_trips_argv() {
+        let invocation = Invocation::Argv(argv(&["sed", "-i", "s/a b/c/", "x y.txt"]));
+        let message = invocation.message().to_string();
+        assert_eq!(
+            Invocation::parse(&message, false).unwrap(),
+            Some(invocation)
+        );
+    }
+
+    #[test]
+    fn parse_round_trips_script_with_blank_lines() {
+        let invocation = Invocation::Shell {
+            shell: Shell::new("zsh"),
+            script: "echo a\n\necho b\n\n".into(),
+        };
+        let message = invocation.message().to_string();
+        assert_eq!(
+            Invocation::parse(&message, false).unwrap(),
+            Some(invocation)
+        );
+    }
+
+    #[test]
+    fn parse_legacy() {
+        assert_eq!(
+            Invocation::parse("run: cargo fmt --all\n", false).unwrap(),
+            Some(Invocation::Argv(argv(&["cargo", "fmt", "--all"])))
+        );
+        assert_eq!(
+            Invocation::parse("run: cargo fmt && cargo clippy\n", true).unwrap(),
+            Some(Invocation::Shell {
+                shell: Shell::bash(),
+                script: "cargo fmt && cargo clippy".into(),
+            })
+        );
+        assert_eq!(Invocation::parse("fix: a bug\n", false).unwrap(), None);
+        assert_eq!(Invocation::parse("run: \n", false).unwrap(), None);
+    }
+
+    #[test]
+    fn program() {
+        let script = |script: &str| Invocation::Shell {
+            shell: Shell::new("/bin/zsh"),
+            script: script.into(),
+        };
+        assert_eq!(script("cargo fmt").program(), "cargo");
+        assert_eq!(script("true; rm q").program(), "true");
+        assert_eq!(script("/usr/bin/make && make test").program(), "make");
+        assert_eq!(script("(cd src && make)").program(), "zsh");
+        assert_eq!(script("X=1 make").program(), "zsh");
+        assert_eq!(script("echo 'unclosed").program(), "zsh");
+        assert_eq!(script("").program(), "zsh");
+        assert_eq!(
+            Invocation::Argv(argv(&["./scripts/gen.sh", "-v"])).program(),
+            "gen.sh"
+        );
+    }
+
+    #[test]
+    fn parse_rejects_bad_trailers() {
+        assert!(Invocation::parse("run: x\n\nRun-Argv: []\n", false).is_err());
+        assert!(
+            Invocation::parse("run: x\n\nRun-Shell: sh\nRun-Argv: [\"a\",\"b\"]\n", false).is_err()
+        );
+    }
+
+    fn from_message(message: Message) -> Recorded {
+        let message = message.to_string();
+        Recorded {
+            invocation: Invocation::parse(&message, false).unwrap().unwrap(),
+            message,
+        }
+    }
+
+    fn make() -> Message {
+        Invocation::Argv(argv(&["make"])).message()
+    }
+
+    #[test]
+    fn recorded_defaults() {
+        let recorded = from_message(make());
+        assert_eq!(recorded.exit_code().unwrap(), 0);
+        assert_eq!(recorded.cwd().unwrap(), PathBuf::new());
+        assert!(recorded.pathspecs().unwrap().is_empty());
+    }
+
+    #[test]
+    fn recorded_trailers() {
+        let recorded = from_message(
+            make()
+                .trailer(RUN_EXIT_CODE, "3")
+                .trailer(RUN_CWD, "src/sub")
+                .trailer(RUN_INCLUDE, encode(["src", "docs"]))
+                .trailer(RUN_EXCLUDE, encode(["*.log"])),
+        );
+        assert_eq!(recorded.exit_code().unwrap(), 3);
+        assert_eq!(recorded.cwd().unwrap(), PathBuf::from("src/sub"));
+        assert_eq!(
+            recorded.pathspecs().unwrap(),
+            argv(&["src", "docs", ":(exclude)*.log"])
+        );
+    }
+
+    #[test]
+    fn recorded_malformed() {
+        assert!(from_message(make().trailer(RUN_EXIT_CODE, "one"))
+            .exit_code()
+            .is_err());
+        for cwd in ["../outside", "/absolute", "a/../b", "./a"] {
+            assert!(
+                from_message(make().trailer(RUN_CWD, cwd)).cwd().is_err(),
+                "{cwd}"
+            );
+        }
+        assert!(from_message(make().trailer(RUN_INCLUDE, "src"))
+            .pathspecs()
+            .is_err());
+    }
+
+    #[test]
+    fn rerun_stages_only_what_was_committed() {
+        let repository = crate::testing::Repository::new();
+        let recorded = from_message(
+            Invocation::Argv(argv(&["sh", "-c", "echo gen > gen.rs && date > log.txt"]))
+                .message()
+                .trailer(RUN_EXCLUDE, encode(["log.txt"])),
+        );
+        recorded.rerun(repository.path()).unwrap();
+        assert_eq!(
+            repository.git(&["diff", "--cached", "--name-only"]),
+            "gen.rs\n"
+        );
+        assert_eq!(repository.read("gen.rs").as_deref(), Some("gen\n"));
+
+        let recorded = from_message(
+            Invocation::Argv(argv(&["sh", "-c", "exit 2"]))
+                .message()
+                .trailer(RUN_EXIT_CODE, "1"),
+        );
+        assert!(recorded.rerun(repository.path()).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+//! Runs a command, then writes a commit saying what was run.
+//!
+//! This is what the `git-run` binary does, as a library:
+//! describe the command with a [`RunSpec`], run it with a [`Runner`],
+//! and commit the [`RunOutcome`].
+
+mod backend;
+mod batch;
+mod capture;
+mod cli;
+mod config;
+mod environment;
+mod error;
+mod invocation;
+mod log;
+mod message;
+mod rebase;
+mod replay;
+mod report;
+mod review;
+mod runner;
+mod shell;
+mod signals;
+mod snapshot;
+mod template;
+#[cfg(test)]
+mod testing;
+mod timeout;
+mod worktree;
+
+use anyhow::{bail, Context};
+use std::{
+    ffi::OsString,
+    io::{self, Read as _},
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+#[doc(hidden)]
+pub use cli::main;
+pub use error::{ErrorKind, Failure};
+pub use message::Message;
+pub use runner::{Files, RunOutcome, RunSpec, Runner};
+
+const NOTES_REF: &str = "refs/notes/git-run";
+/// Where the input given with `--stdin` is kept, so that it isn't garbage collected.
+const STDIN_REFS: &str = "refs/git-run/stdin/";
+
+fn run(command: &mut Command) -> anyhow::Result<(&mut Command, Output)> {
+    let (program, args) = get_program_and_args(command);
+    tracing::debug!(?program, ?args, "running");
+    let exit_status = command
+        .output()
+        .with_context(|| format!("couldn't run {program:?} with arguments {args:?}"))?;
+    tracing::trace!(status = %exit_status.status, "ran");
+    Ok((command, exit_status))
+}
+
+/// Whether `command` prints anything, without waiting for the rest if it does.
+fn prints_anything(command: &mut Command) -> anyhow::Result<bool> {
+    let (program, args) = get_program_and_args(command);
+    tracing::debug!(?program, ?args, "running until it prints");
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .with_context(|| format!("couldn't run {program:?} with arguments {args:?}"))?;
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let printed = match stdout.read_exact(&mut [0]) {
+        Ok(()) => true,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => false,
+        Err(e) => return Err(e.into()),
+    };
+    // which may kill it with SIGPIPE, so its status only matters if it printed nothing
+    drop(stdout);
+    let status = child.wait()?;
+    if !printed {
+        errexit((
+            command,
+            Output {
+                status,
+                stdout: vec![],
+                stderr: vec![],
+            },
+        ))?;
+    }
+    Ok(printed)
+}
+
+fn get_program_and_args(command: &Command) -> (OsString, Vec<OsString>) {
+    (
+        command.get_program().into(),
+        command.get_args().map(Into::into).collect(),
+    )
+}
+
+fn errexit(ran: (&mut Command, Output)) -> anyhow::Result<Output> {
+    expect_exit(ran, |it| it == 0)
+}
+
+/// Like [`errexit`], but `ok` decides which exit codes are a success.
+fn expect_exit(
+    (command, output): (&mut Command, Output),
+    ok: impl FnOnce(i32) -> bool,
+) -> anyhow::Result<Output> {
+    let (program, args) = get_program_and_args(command);
+    match output.status.code() {
+        Some(code) if ok(code) => Ok(output),
+        Some(0) => {
+            bail!("program {program:?} with arguments {args:?} succeeded, but was expected to fail")
+        }
+        Some(nonzero) => {
+            bail!("program {program:?} with arguments {args:?} failed with status {nonzero}")
+        }
+        None => match signals::of(&output.status) {
+            Some(signal) => bail!(
+                "program {program:?} with arguments {args:?} was killed by {}",
+                signals::name(signal)
+            ),
+            None => bail!("program {program:?} with arguments {args:?} failed with no status"),
+        },
+    }
+}
+
+/// Whether the repository at `root` has no dirty or untracked files.
+fn is_clean(root: &Path) -> anyhow::Result<bool> {
+    backend::open(root)?.is_clean()
+}
+
+fn read(command: &mut Command) -> anyhow::Result<String> {
+    let output = errexit(run(command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit()))?)?;
+    String::from_utf8(output.stdout).context("command printed invalid UTF-8")
+}
+
+/// The path of the current directory, relative to the top of the repository.
+fn prefix() -> anyhow::Result<PathBuf> {
+    Ok(read(git().args(["rev-parse", "--show-prefix"]))?
+        .trim_end_matches('\n')
+        .trim_end_matches('/')
+        .into())
+}
+
+/// The top of the repository containing `dir`.
+fn toplevel(dir: &Path) -> anyhow::Result<PathBuf> {
+    Ok(read(
+        git()
+            .current_dir(dir)
+            .args(["rev-parse", "--show-toplevel"]),
+    )?
+    .trim_end_matches('\n')
+    .into())
+}
+
+fn git() -> Command {
+    Command::new("git")
+}
+
+fn visible(command: &mut Command) -> &mut Command {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+}
@@ -0,0 +1,76 @@
+//! Diagnostics, with `tracing`.
+//!
+//! Each phase of a run is a span, so that `-v` shows where time goes and where things fail.
+
+use anyhow::Context as _;
+use std::{fs::File, io, path::PathBuf, sync::Arc};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{
+    fmt, layer::SubscriberExt as _, util::SubscriberInitExt as _, EnvFilter, Layer as _,
+};
+
+#[derive(Debug, clap::Args)]
+#[group(id = "log")]
+pub struct Args {
+    /// Log more about what's happening, to stderr.
+    ///
+    /// May be given up to three times.
+    /// `RUST_LOG` takes precedence, and may filter by module, as in `RUST_LOG=git_run::runner=debug`.
+    #[arg(short, long, action = clap::ArgAction::Count, global = true, conflicts_with = "quiet")]
+    verbose: u8,
+    /// Log only errors, or with `-qq`, nothing.
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    quiet: u8,
+    /// Also write the log to FILE as lines of JSON, at least at the debug level.
+    #[arg(long, value_name = "FILE", global = true)]
+    log_file: Option<PathBuf>,
+}
+
+/// Start logging, as `args` and `RUST_LOG` say to.
+pub fn init(args: &Args) -> anyhow::Result<()> {
+    let level = match (args.verbose, args.quiet) {
+        (0, 0) => LevelFilter::WARN,
+        (0, 1) => LevelFilter::ERROR,
+        (0, _) => LevelFilter::OFF,
+        (1, _) => LevelFilter::INFO,
+        (2, _) => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+    let stderr = fmt::layer()
+        .with_writer(io::stderr)
+        .with_target(false)
+        .with_filter(filter(level));
+    let file = match &args.log_file {
+        Some(path) => {
+            let file = File::create(path)
+                .with_context(|| format!("couldn't create log file {}", path.display()))?;
+            Some(
+                fmt::layer()
+                    .json()
+                    .with_writer(Arc::new(file))
+                    .with_span_list(true)
+                    .with_filter(filter(level.max(LevelFilter::DEBUG))),
+            )
+        }
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(stderr)
+        .with(file)
+        .try_init()?;
+    Ok(())
+}
+
+/// `RUST_LOG` if it's set, otherwise `level` for this crate and warnings from others.
+fn filter(level: LevelFilter) -> EnvFilter {
+    match std::env::var_os(EnvFilter::DEFAULT_ENV) {
+        Some(_) => EnvFilter::from_default_env(),
+        None => EnvFilter::default()
+            .add_directive(LevelFilter::WARN.min(level).into())
+            .add_directive(
+                format!("git_run={level}")
+                    .parse()
+                    .expect("directive is valid"),
+            ),
+    }
+}
@@ -0,0 +1,102 @@
+//! Commit messages, and the trailers git-run records in them.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Message {
+    pub subject: String,
+    pub body: String,
+    pub trailers: Vec<(String, String)>,
+}
+
+impl Message {
+    pub fn new(subject: impl Into<String>) -> Self {
+        Self {
+            subject: subject.into(),
+            body: String::new(),
+            trailers: vec![],
+        }
+    }
+
+    pub fn trailer(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.trailers.push((key.into(), value.into()));
+        self
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.subject)?;
+        if !self.body.is_empty() {
+            write!(f, "\n\n{}", self.body)?
+        }
+        for (ix, (key, value)) in self.trailers.iter().enumerate() {
+            match ix {
+                0 => f.write_str("\n\n")?,
+                _ => f.write_str("\n")?,
+            }
+            write!(f, "{key}: {value}")?
+        }
+        Ok(())
+    }
+}
+
+/// The `Key: value` trailers in the last paragraph of `message`.
+pub fn trailers(message: &str) -> Vec<(&str, &str)> {
+    let message = message.trim_end();
+    let Some((_, last)) = message.rsplit_once("\n\n") else {
+        return vec![];
+    };
+    last.lines()
+        .filter_map(|line| line.split_once(": "))
+        .filter(|(key, _)| !key.is_empty() && !key.contains(char::is_whitespace))
+        .collect()
+}
+
+/// The value of the last trailer in `message` called `key`, compared case-insensitively.
+pub fn trailer<'a>(message: &'a str, key: &str) -> Option<&'a str> {
+    trailers(message)
+        .into_iter()
+        .rev()
+        .find(|(it, _)| it.eq_ignore_ascii_case(key))
+        .map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trailers_are_in_the_last_paragraph() {
+        let message =
+            "run: echo a\n\necho b\n\nRun-Shell: bash\nRun-Argv: [\"echo a\\n\\necho b\"]\n";
+        assert_eq!(
+            trailers(message),
+            [
+                ("Run-Shell", "bash"),
+                ("Run-Argv", "[\"echo a\\n\\necho b\"]")
+            ]
+        );
+        assert_eq!(trailer(message, "run-shell"), Some("bash"));
+    }
+
+    #[test]
+    fn no_trailers_without_a_body() {
+        assert!(trailers("Run-Shell: bash\n").is_empty());
+        assert_eq!(trailer("run: true", "Run-Argv"), None);
+    }
+
+    #[test]
+    fn the_last_trailer_wins() {
+        let message = "subject\n\nKey: one\nKey: two";
+        assert_eq!(trailer(message, "Key"), Some("two"));
+    }
+
+    #[test]
+    fn display() {
+        let mut message = Message::new("subject").trailer("Key", "value");
+        assert_eq!(message.to_string(), "subject\n\nKey: value");
+        message.body = "body".into();
+        assert_eq!(message.to_string(), "subject\n\nbody\n\nKey: value");
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{backend, errexit, git, invocation, is_clean, read, run, toplevel, visible};
+use anyhow::{bail, Context as _};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Interpret recorded commands as bash scripts.
+    ///
+    /// Only needed for commits made before git-run recorded a `Run-Argv` trailer.
+    #[arg(short, long)]
+    shell: bool,
+    /// The upstream to rebase onto, as for `git rebase`.
+    upstream: String,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct RegenerateArgs {
+    /// Interpret the recorded command as a bash script.
+    ///
+    /// Only needed for commits made before git-run recorded a `Run-Argv` trailer.
+    #[arg(short, long)]
+    shell: bool,
+    /// The `run:` commit to regenerate.
+    commit: String,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct EditTodoArgs {
+    #[arg(short, long)]
+    shell: bool,
+    /// The todo list written by `git rebase --interactive`.
+    todo: PathBuf,
+}
+
+/// Start an interactive rebase, using ourselves as the sequence editor
+/// to swap `pick`s of `run:` commits for [`regenerate`].
+pub fn main(args: Args) -> anyhow::Result<()> {
+    let editor = git_run(&["edit-todo"], args.shell)?;
+    errexit(run(visible(
+        git()
+            .args(["rebase", "--interactive", "--end-of-options"])
+            .arg(&args.upstream)
+            .env("GIT_SEQUENCE_EDITOR", editor),
+    ))?)?;
+    Ok(())
+}
+
+pub fn edit_todo(args: EditTodoArgs) -> anyhow::Result<()> {
+    let todo = fs::read_to_string(&args.todo)
+        .with_context(|| format!("couldn't read todo list {}", args.todo.display()))?;
+    let edited = edit(&todo, Path::new("."), args.shell)?;
+    fs::write(&args.todo, edited)
+        .with_context(|| format!("couldn't write todo list {}", args.todo.display()))
+}
+
+/// `todo` with the `pick`s of `run:` commits in the repository at `root` swapped for [`regenerate`].
+fn edit(todo: &str, root: &Path, shell: bool) -> anyhow::Result<String> {
+    let mut edited = String::with_capacity(todo.len());
+    for line in todo.lines() {
+        match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["pick" | "p", commit, ..] => {
+                let commit = read(git().current_dir(root).args([
+                    "rev-parse",
+                    "--verify",
+                    &format!("{commit}^{{commit}}"),
+                ]))?;
+                let commit = commit.trim();
+                match invocation::recorded(root, commit, shell)? {
+                    Some(_) => {
+                        edited.push_str("exec ");
+                        edited.push_str(&git_run(&["regenerate", commit], shell)?);
+                        edited.push_str(" # ");
+                        edited.push_str(line);
+                    }
+                    None => edited.push_str(line),
+                }
+            }
+            _ => edited.push_str(line),
+        }
+        edited.push('\n');
+    }
+    Ok(edited)
+}
+
+pub fn regenerate(args: RegenerateArgs) -> anyhow::Result<()> {
+    let commit = args.commit.as_str();
+    let root = toplevel(Path::new("."))?;
+    let Some(recorded) = invocation::recorded(&root, commit, args.shell)? else {
+        bail!("{commit} is not a `run:` commit")
+    };
+
+    if !is_clean(&root)? {
+        bail!("there are dirty or untracked files, so {commit} can't be regenerated")
+    }
+
+    recorded.rerun(&root)?;
+
+    match backend::open(&root)?.staged()?.is_empty() {
+        true => println!(
+            "`{}` made no changes, dropping it",
+            recorded.invocation.subject()
+        ),
+        false => errexit(run(visible(git().current_dir(&root).args([
+            "commit",
+            "--quiet",
+            "--reuse-message",
+            commit,
+        ])))?)
+        .map(drop)?,
+    }
+
+    // the rebase can't carry on with the changes which were left out
+    if !is_clean(&root)? {
+        errexit(run(visible(
+            git()
+                .current_dir(&root)
+                .args(["reset", "--hard", "--quiet"]),
+        ))?)?;
+        errexit(run(visible(
+            git()
+                .current_dir(&root)
+                .args(["clean", "-d", "--force", "--quiet"]),
+        ))?)?;
+        eprintln!("discarded the changes which weren't included when {commit} was made");
+    }
+    Ok(())
+}
+
+/// A shell command line which invokes this executable with `args`.
+fn git_run(args: &[&str], shell: bool) -> anyhow::Result<String> {
+    let exe = env::current_exe().context("couldn't find the git-run executable")?;
+    let exe = exe
+        .to_str()
+        .context("the path to the git-run executable is not valid UTF-8")?;
+    Ok(shell_words::join(
+        [exe]
+            .into_iter()
+            .chain(args.iter().copied())
+            .chain(shell.then_some("--shell")),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{invocation::Invocation, testing::Repository};
+
+    #[test]
+    fn edits_todo() {
+        let repository = Repository::new();
+        repository.write("a", "a\n");
+        repository.commit("by hand");
+        let by_hand = repository.git(&["rev-parse", "HEAD"]);
+        repository.write("b", "b\n");
+        let message = Invocation::Argv(vec!["touch".into(), "b".into()])
+            .message()
+            .to_string();
+        repository.commit(&message);
+        let run = repository.git(&["rev-parse", "HEAD"]);
+        let (by_hand, run) = (by_hand.trim(), run.trim());
+
+        let todo = format!(
+            "pick {} by hand\np {} run: touch b\n\n# Rebase onto ...\nexec make\n",
+            &by_hand[..7],
+            &run[..7]
+        );
+        let edited = edit(&todo, repository.path(), false).unwrap();
+        let lines = edited.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], format!("pick {} by hand", &by_hand[..7]));
+        assert!(lines[1].starts_with("exec "), "{}", lines[1]);
+        assert!(
+            lines[1].ends_with(&format!(" regenerate {run} # p {} run: touch b", &run[..7])),
+            "{}",
+            lines[1]
+        );
+        assert_eq!(lines[2..], ["", "# Rebase onto ...", "exec make"]);
+
+        let edited = edit(&todo, repository.path(), true).unwrap();
+        assert!(edited.lines().nth(1).unwrap().contains(" --shell # p "));
+    }
+}
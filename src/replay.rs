@@ -0,0 +1,94 @@
+use crate::{
+    errexit, git,
+    invocation::{self, Recorded},
+    read, run, visible,
+    worktree::Worktree,
+};
+use anyhow::bail;
+use std::path::Path;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Interpret recorded commands as bash scripts.
+    ///
+    /// Only needed for commits made before git-run recorded a `Run-Argv` trailer.
+    #[arg(short, long)]
+    shell: bool,
+    /// The commits to replay, as understood by `git rev-list`.
+    ///
+    /// For example, `main..HEAD`.
+    #[arg(num_args(1..), required = true)]
+    revisions: Vec<String>,
+}
+
+enum Verdict {
+    Reproduced,
+    Differs,
+    Failed(anyhow::Error),
+}
+
+/// Re-run each `run:` commit's command on its parent in a scratch worktree,
+/// and check that the result is the recorded tree.
+pub fn main(args: Args) -> anyhow::Result<()> {
+    let commits = read(
+        git()
+            .args(["rev-list", "--reverse", "--end-of-options"])
+            .args(&args.revisions),
+    )?;
+
+    let mut bad = 0;
+    for commit in commits.lines() {
+        let Some(recorded) = invocation::recorded(Path::new("."), commit, args.shell)? else {
+            println!("{commit} skipped: not a `run:` commit");
+            continue;
+        };
+        let Ok(parent) = read(
+            git()
+                .args(["rev-parse", "--verify", "--quiet"])
+                .arg(format!("{commit}^")),
+        ) else {
+            println!("{commit} skipped: no parent to replay on");
+            continue;
+        };
+
+        let verdict = match replay(commit, parent.trim(), &recorded) {
+            Ok(true) => Verdict::Reproduced,
+            Ok(false) => Verdict::Differs,
+            Err(e) => Verdict::Failed(e),
+        };
+        let message = recorded.invocation.subject();
+        match verdict {
+            Verdict::Reproduced => println!("{commit} reproduced: {message}"),
+            Verdict::Differs => {
+                bad += 1;
+                println!("{commit} differs: {message}")
+            }
+            Verdict::Failed(e) => {
+                bad += 1;
+                println!("{commit} failed: {message}: {e:#}")
+            }
+        }
+    }
+
+    match bad {
+        0 => Ok(()),
+        n => bail!("{n} commit(s) did not reproduce"),
+    }
+}
+
+fn replay(commit: &str, parent: &str, recorded: &Recorded) -> anyhow::Result<bool> {
+    let worktree = Worktree::add(Path::new("."), parent)?;
+    recorded.rerun(worktree.path())?;
+    let replayed = read(git().current_dir(worktree.path()).arg("write-tree"))?;
+    let recorded = read(git().args(["rev-parse", &format!("{commit}^{{tree}}")]))?;
+    if replayed.trim() == recorded.trim() {
+        return Ok(true);
+    }
+    errexit(run(visible(git().args([
+        "diff",
+        "--stat",
+        recorded.trim(),
+        replayed.trim(),
+    ])))?)?;
+    Ok(false)
+}
@@ -0,0 +1,115 @@
+//! The machine-readable report printed by `--output json`.
+
+use crate::{
+    error::{ErrorKind, Failure},
+    invocation::Invocation,
+    runner::RunOutcome,
+    signals,
+};
+use serde::Serialize;
+use std::{collections::BTreeSet, path::PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Format {
+    /// Progress and errors for people.
+    #[default]
+    Human,
+    /// One line of JSON per command on stdout, with everything else on stderr.
+    Json,
+}
+
+/// What happened when running one command.
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    command: String,
+    shell: Option<String>,
+    exit_code: Option<i32>,
+    /// The name of the signal which interrupted the command.
+    signal: Option<String>,
+    /// In seconds.
+    duration: Option<f64>,
+    files: Files,
+    commit: Option<String>,
+    error: Option<Error>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Files {
+    added: Vec<String>,
+    modified: Vec<String>,
+    deleted: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Error {
+    kind: ErrorKind,
+    message: String,
+}
+
+impl Report {
+    pub fn new(invocation: &Invocation) -> Self {
+        Self {
+            command: invocation.command_line(),
+            shell: match invocation {
+                Invocation::Shell { shell, .. } => Some(shell.program().into()),
+                Invocation::Argv(_) => None,
+            },
+            ..Self::default()
+        }
+    }
+
+    /// A report for an error which came before there was a command to run, such as invalid
+    /// settings, in which case [`Self::new`]'s `command` is empty.
+    pub fn unstarted(invocation: Option<&Invocation>, error: &anyhow::Error) -> Self {
+        let mut report = match invocation {
+            Some(invocation) => Self::new(invocation),
+            None => Self::default(),
+        };
+        report.failed(error);
+        report
+    }
+
+    /// Record how the command went.
+    pub fn ran(&mut self, outcome: &RunOutcome) -> anyhow::Result<()> {
+        let files = outcome.files()?;
+        self.exit_code = Some(outcome.exit_code);
+        self.signal = outcome.signal.map(signals::name);
+        self.duration = Some(outcome.duration.as_secs_f64());
+        self.files = Files {
+            added: strings(&files.added),
+            modified: strings(&files.modified),
+            deleted: strings(&files.deleted),
+        };
+        Ok(())
+    }
+
+    pub fn committed(&mut self, commit: String) {
+        self.commit = Some(commit)
+    }
+
+    pub fn failed(&mut self, error: &anyhow::Error) {
+        if let Some(failure) = error.downcast_ref::<Failure>() {
+            self.exit_code = self.exit_code.or(failure.exit_code);
+            self.signal = self.signal.take().or(failure.signal.map(signals::name));
+            self.duration = self
+                .duration
+                .or(failure.duration.map(|it| it.as_secs_f64()));
+        }
+        self.error = Some(Error {
+            kind: ErrorKind::of(error),
+            message: format!("{error:#}"),
+        })
+    }
+
+    pub fn print(&self) -> anyhow::Result<()> {
+        println!("{}", serde_json::to_string(self)?);
+        Ok(())
+    }
+}
+
+fn strings(paths: &BTreeSet<PathBuf>) -> Vec<String> {
+    paths
+        .iter()
+        .map(|it| it.to_string_lossy().into_owned())
+        .collect()
+}
@@ -0,0 +1,166 @@
+//! Reviewing a command's staged changes file by file before they are committed.
+
+use crate::{backend, errexit, git, run, runner::shown, visible};
+use anyhow::{bail, Context as _};
+use dialoguer::{Editor, Select};
+use std::{
+    collections::BTreeSet,
+    io::{self, IsTerminal as _},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// Whether there's a terminal to ask the user with.
+///
+/// Without one, prompts would wait for a key forever.
+pub fn can_prompt() -> bool {
+    io::stdin().is_terminal() && io::stderr().is_terminal()
+}
+
+/// What the user chose to commit.
+pub struct Review {
+    /// The paths with staged changes to commit.
+    pub paths: BTreeSet<PathBuf>,
+    /// Whether only some hunks of a path are staged,
+    /// in which case the index must be committed as it is.
+    pub partial: bool,
+    pub message: String,
+}
+
+/// Walk through the staged changes to `changed`, which are relative to `root`,
+/// and then the commit `message`.
+/// If `stdout_to_stderr`, what's shown goes to stderr.
+///
+/// Returns [`None`] if the user aborts.
+pub fn review(
+    root: &Path,
+    changed: &BTreeSet<PathBuf>,
+    message: String,
+    stdout_to_stderr: bool,
+) -> anyhow::Result<Option<Review>> {
+    if !can_prompt() {
+        bail!("reviewing the changes needs a terminal");
+    }
+    // hunks are committed from the index, which mustn't have anything else in it
+    let staged = backend::open(root)?.staged()?;
+    let hunks = staged.is_subset(changed);
+
+    let mut paths = BTreeSet::new();
+    let mut partial = false;
+    for path in changed {
+        errexit(run(shown(
+            git_in(root)
+                .args([
+                    "-c",
+                    "color.diff=always",
+                    "--literal-pathspecs",
+                    "diff",
+                    "--cached",
+                    "--stat",
+                    "--patch",
+                    "--",
+                ])
+                .arg(path),
+            stdout_to_stderr,
+        ))?)?;
+        let mut items = vec!["commit this file", "leave this file out"];
+        if hunks {
+            items.push("choose hunks");
+        }
+        items.push("abort");
+        let choice = Select::new()
+            .with_prompt(path.display().to_string())
+            .items(&items)
+            .default(0)
+            .interact_opt()?;
+        match choice.map(|it| items[it]) {
+            Some("commit this file") => {
+                paths.insert(path.clone());
+            }
+            Some("leave this file out") => unstage(root, path)?,
+            Some("choose hunks") => {
+                unstage(root, path)?;
+                // so that new files can be split up too
+                errexit(run(visible(
+                    git_in(root)
+                        .args(["--literal-pathspecs", "add", "--intent-to-add", "--"])
+                        .arg(path),
+                ))?)?;
+                errexit(run(shown(
+                    git_in(root)
+                        .args(["--literal-pathspecs", "add", "--patch", "--"])
+                        .arg(path),
+                    stdout_to_stderr,
+                )
+                .stdin(Stdio::inherit()))?)?;
+                let mut git = git_in(root);
+                let (_, output) = run(git
+                    .args(["--literal-pathspecs", "diff", "--cached", "--quiet", "--"])
+                    .arg(path))?;
+                match output.status.success() {
+                    true => unstage(root, path)?,
+                    false => {
+                        paths.insert(path.clone());
+                        partial = true;
+                    }
+                }
+            }
+            _ => return Ok(None),
+        }
+    }
+    if paths.is_empty() {
+        eprintln!("all of the changes were left out");
+        return Ok(None);
+    }
+
+    let mut message = message;
+    loop {
+        match stdout_to_stderr {
+            true => eprintln!("\n{}\n", message.trim_end()),
+            false => println!("\n{}\n", message.trim_end()),
+        }
+        let items = ["commit", "edit the message", "abort"];
+        let choice = Select::new()
+            .with_prompt("commit with this message")
+            .items(&items)
+            .default(0)
+            .interact_opt()?;
+        match choice {
+            Some(0) => break,
+            Some(1) => {
+                if let Some(edited) = Editor::new()
+                    .edit(&message)
+                    .context("couldn't edit the message")?
+                {
+                    match edited.trim().is_empty() {
+                        true => eprintln!("the message is empty, so keeping the old one"),
+                        false => message = edited,
+                    }
+                }
+            }
+            _ => return Ok(None),
+        }
+    }
+
+    Ok(Some(Review {
+        paths,
+        partial,
+        message,
+    }))
+}
+
+/// Put the index entry for `path` back to HEAD, leaving the worktree alone.
+fn unstage(root: &Path, path: &Path) -> anyhow::Result<()> {
+    errexit(run(visible(
+        git_in(root)
+            .args(["--literal-pathspecs", "reset", "--quiet", "--"])
+            .arg(path),
+    ))?)?;
+    Ok(())
+}
+
+fn git_in(root: &Path) -> Command {
+    let mut git = git();
+    git.current_dir(root);
+    git
+}
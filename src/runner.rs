@@ -0,0 +1,762 @@
+//! Running a command and committing what it changed, as `git run` does.
+
+use crate::{
+    backend, capture,
+    environment::Environment,
+    errexit,
+    error::{ErrorKind, Failure},
+    expect_exit, git,
+    invocation::{
+        encode, store_stdin, Invocation, RUN_CWD, RUN_DURATION, RUN_EXCLUDE, RUN_EXIT_CODE,
+        RUN_INCLUDE, RUN_SIGNAL, RUN_STDIN,
+    },
+    is_clean,
+    message::Message,
+    read, run,
+    shell::Shell,
+    signals::{self, Forward},
+    snapshot::{self, Snapshot},
+    timeout::Timeout,
+    toplevel, visible,
+    worktree::Worktree,
+    NOTES_REF, STDIN_REFS,
+};
+use anyhow::{anyhow, bail, Context as _};
+use itertools::Itertools as _;
+use std::{
+    collections::BTreeSet,
+    ffi::OsString,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+use tempfile::NamedTempFile;
+use tracing::{debug, info, info_span};
+
+/// What to run, and where.
+#[derive(Debug, Clone)]
+pub struct RunSpec {
+    pub(crate) invocation: Invocation,
+    cwd: PathBuf,
+    env: Environment,
+    stdin: Option<PathBuf>,
+}
+
+impl RunSpec {
+    /// Run the program `argv[0]`, with the rest of `argv` as its arguments.
+    pub fn argv<I>(argv: I) -> anyhow::Result<Self>
+    where
+        I: IntoIterator,
+        I::Item: Into<OsString>,
+    {
+        let argv = argv.into_iter().map(Into::into).collect::<Vec<_>>();
+        if argv.is_empty() {
+            bail!("the command is empty")
+        }
+        Ok(Self::new(Invocation::Argv(argv)))
+    }
+
+    /// Run `script` with `shell`, as `git run --shell=SHELL` does.
+    pub fn script(shell: impl Into<String>, script: impl Into<OsString>) -> Self {
+        Self::new(Invocation::Shell {
+            shell: Shell::new(shell),
+            script: script.into(),
+        })
+    }
+
+    pub(crate) fn new(invocation: Invocation) -> Self {
+        Self {
+            invocation,
+            cwd: PathBuf::new(),
+            env: Environment::default(),
+            stdin: None,
+        }
+    }
+
+    /// Run in `cwd`, relative to the top of the repository, instead of at the top.
+    pub fn cwd(mut self, cwd: impl Into<PathBuf>) -> Self {
+        self.cwd = cwd.into();
+        self
+    }
+
+    /// Set the environment variable `key` for the command.
+    pub fn env(mut self, key: impl Into<OsString>, value: impl Into<OsString>) -> Self {
+        self.env.set(key.into(), value.into());
+        self
+    }
+
+    /// Whether to start the command with an empty environment, rather than inheriting ours.
+    pub fn env_clear(mut self, clear: bool) -> Self {
+        self.env.clear = clear;
+        self
+    }
+
+    /// Keep our environment variables whose names match `glob`, which implies [`Self::env_clear`].
+    ///
+    /// In `glob`, `*` matches any run of characters and `?` any one.
+    pub fn env_keep(mut self, glob: impl Into<String>) -> Self {
+        self.env.clear = true;
+        self.env.keep.push(glob.into());
+        self
+    }
+
+    /// Feed the contents of the file at `path` to the command's stdin, and record them so that
+    /// the run may be replayed.
+    pub fn stdin(mut self, path: impl Into<PathBuf>) -> Self {
+        self.stdin = Some(path.into());
+        self
+    }
+
+    /// The command line, as it would be typed into a shell.
+    pub fn command_line(&self) -> String {
+        self.invocation.command_line()
+    }
+}
+
+/// How to run a [`RunSpec`], and which of its changes to keep.
+#[derive(Debug, Clone)]
+pub struct Runner {
+    repository: PathBuf,
+    allow_dirty: bool,
+    worktree: bool,
+    branch: Option<String>,
+    capture: bool,
+    allow_failure: bool,
+    expect_exit: Vec<i32>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    rollback: bool,
+    stdout_to_stderr: bool,
+    interactive: bool,
+    allow_signal: bool,
+    timeout: Option<Duration>,
+}
+
+impl Default for Runner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Runner {
+    pub fn new() -> Self {
+        Self {
+            repository: PathBuf::from("."),
+            allow_dirty: false,
+            worktree: false,
+            branch: None,
+            capture: false,
+            allow_failure: false,
+            expect_exit: vec![],
+            include: vec![],
+            exclude: vec![],
+            rollback: true,
+            stdout_to_stderr: false,
+            interactive: false,
+            allow_signal: false,
+            timeout: None,
+        }
+    }
+
+    /// Run in the repository containing `path`, rather than the one containing the current directory.
+    pub fn repository(mut self, path: impl Into<PathBuf>) -> Self {
+        self.repository = path.into();
+        self
+    }
+
+    /// Allow dirty or untracked files, and keep only the paths that the command changed.
+    pub fn allow_dirty(mut self, allow_dirty: bool) -> Self {
+        self.allow_dirty = allow_dirty;
+        self
+    }
+
+    /// Run in a temporary worktree at HEAD, which the current branch is fast-forwarded from on commit.
+    pub fn worktree(mut self, worktree: bool) -> Self {
+        self.worktree = worktree;
+        self
+    }
+
+    /// With [`Self::worktree`], create `branch` on commit instead of fast-forwarding.
+    pub fn branch(mut self, branch: Option<String>) -> Self {
+        self.branch = branch;
+        self
+    }
+
+    /// Keep a copy of the command's output, to attach to the commit as a note.
+    pub fn capture(mut self, capture: bool) -> Self {
+        self.capture = capture;
+        self
+    }
+
+    /// Keep the command's changes even if it exits with a non-zero status.
+    pub fn allow_failure(mut self, allow_failure: bool) -> Self {
+        self.allow_failure = allow_failure;
+        self
+    }
+
+    /// Keep the command's changes only if it exits with one of `codes`, rather than `0`.
+    pub fn expect_exit(mut self, codes: impl IntoIterator<Item = i32>) -> Self {
+        self.expect_exit.extend(codes);
+        self
+    }
+
+    /// Only keep changed paths which match one of `pathspecs`, relative to the top of the repository.
+    pub fn include(mut self, pathspecs: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.include.extend(pathspecs.into_iter().map(Into::into));
+        self
+    }
+
+    /// Don't keep changed paths which match one of `pathspecs`, relative to the top of the repository.
+    pub fn exclude(mut self, pathspecs: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.exclude.extend(pathspecs.into_iter().map(Into::into));
+        self
+    }
+
+    /// Whether to put the command's changes back if it fails or they aren't committed.
+    ///
+    /// Defaults to `true`.
+    pub fn rollback(mut self, rollback: bool) -> Self {
+        self.rollback = rollback;
+        self
+    }
+
+    /// Send everything that would go to stdout, including the command's output, to stderr instead.
+    ///
+    /// For when stdout is reserved for a report.
+    pub fn stdout_to_stderr(mut self, stdout_to_stderr: bool) -> Self {
+        self.stdout_to_stderr = stdout_to_stderr;
+        self
+    }
+
+    /// Let the command read from our stdin, for commands which prompt.
+    ///
+    /// Otherwise, its stdin is empty unless [`RunSpec::stdin`] says what it should be.
+    pub fn interactive(mut self, interactive: bool) -> Self {
+        self.interactive = interactive;
+        self
+    }
+
+    /// Keep the command's changes even if it is killed by a signal, or SIGINT or SIGTERM is
+    /// caught while it runs, noting the signal in [`RunOutcome::signal`].
+    ///
+    /// Otherwise they're given up, as when it fails.
+    /// Either way, SIGINT and SIGTERM are passed on to the command rather than killing us.
+    pub fn allow_signal(mut self, allow_signal: bool) -> Self {
+        self.allow_signal = allow_signal;
+        self
+    }
+
+    /// Kill the command, and the rest of its process group, if it runs for longer than `timeout`.
+    ///
+    /// With [`Self::interactive`], the command shares our process group, so only it is killed.
+    /// Its changes are then given up, as when it fails.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Run `spec`, and stage the changes to keep.
+    ///
+    /// Signal handling and the terminal belong to the whole process, so if runs overlap,
+    /// each command waits for the one before it to exit.
+    pub fn run(&self, spec: &RunSpec) -> anyhow::Result<RunOutcome> {
+        let repository = toplevel(&self.repository)?;
+        let worktree = match self.worktree {
+            true => Some(Worktree::add(&repository, "HEAD")?),
+            false => None,
+        };
+        let root = match &worktree {
+            Some(worktree) => worktree.path().to_owned(),
+            None => repository.clone(),
+        };
+        let span = info_span!("clean_check", allow_dirty = self.allow_dirty).entered();
+        let before = match (self.allow_dirty, &worktree) {
+            (true, _) => Snapshot::take(&root)?,
+            (false, Some(_)) => Snapshot::head(&root)?,
+            (false, None) => match is_clean(&root)? {
+                true => Snapshot::head(&root)?,
+                false => return Err(ErrorKind::Dirty.wrap( anyhow!("git-run commits everything that changes, but there are dirty or untracked files before running the command. Pass --allow-dirty to commit only what the command changes."))),
+            },
+        };
+        debug!(tree = %before.tree, "before");
+        span.exit();
+        // a worktree is thrown away anyway
+        let rollback = self.rollback && worktree.is_none();
+
+        let (stdin, blob) = match (&spec.stdin, self.interactive) {
+            (Some(path), _) => (
+                Stdio::from(
+                    File::open(path)
+                        .with_context(|| format!("couldn't open {}", path.display()))?,
+                ),
+                Some(store_stdin(&root, path)?),
+            ),
+            (None, true) => (Stdio::inherit(), None),
+            (None, false) => (Stdio::null(), None),
+        };
+
+        let mut command = spec.invocation.command();
+        command.current_dir(root.join(&spec.cwd));
+        spec.env.apply(&mut command);
+        let span =
+            info_span!("execute", cwd = %spec.cwd.display(), capture = self.capture).entered();
+        let started = Instant::now();
+        let transcript = match self.capture {
+            true => Some(NamedTempFile::new()?),
+            false => None,
+        };
+        let forward = Forward::install(!self.interactive)?;
+        let (command, output, timed_out) = capture::stream(
+            &mut command,
+            stdin,
+            self.stdout_to_stderr,
+            transcript.as_ref().map(NamedTempFile::as_file),
+            &forward,
+            self.timeout,
+        )
+        .map_err(|e| ErrorKind::Spawn.wrap(e))?;
+        let output = (command, output);
+        let duration = started.elapsed();
+        let exit_code = output.1.status.code();
+        let signal = signals::of(&output.1.status).or(forward.received());
+        drop(forward);
+        info!(?exit_code, ?signal, ?duration, "exited");
+        span.exit();
+        let failed = |error| Failure::ran(error, exit_code, duration);
+        let abandon = |error, staged| abandon(&before, rollback, failed(error), staged);
+        if timed_out {
+            let error = anyhow!(
+                "the command was killed after running for longer than {}",
+                Timeout::from(self.timeout)
+            );
+            return Err(abandon(ErrorKind::TimedOut.wrap(error), false));
+        }
+        match signal {
+            Some(signal) if self.allow_signal => {
+                info!(
+                    signal = signals::name(signal),
+                    "interrupted, keeping the changes"
+                )
+            }
+            Some(signal) => {
+                let error = anyhow!("the command was interrupted by {}", signals::name(signal));
+                return Err(abandon(Failure::signalled(signal, error), false));
+            }
+            None => {
+                expect_exit(output, |it| match self.expect_exit.is_empty() {
+                    true => self.allow_failure || it == 0,
+                    false => self.expect_exit.contains(&it),
+                })
+                .map_err(|e| abandon(ErrorKind::CommandFailed.wrap(e), false))?;
+            }
+        }
+
+        let span = info_span!("snapshot").entered();
+        let after = Snapshot::take(&root)?;
+        let touched = before.changed(&after)?;
+        debug!(tree = %after.tree, touched = touched.len(), "after");
+        if touched.is_empty() {
+            return Err(failed(
+                ErrorKind::NoChanges.wrap(anyhow!("the command didn't change any files")),
+            ));
+        }
+        if self.allow_dirty {
+            let dirty = before.dirty()?;
+            let clobbered = touched.intersection(&dirty).collect::<Vec<_>>();
+            if !clobbered.is_empty() {
+                let error = anyhow!(
+                    "the command changed paths which were already dirty, so its changes can't be committed separately: {}",
+                    clobbered.iter().map(|it| it.display()).join(", ")
+                );
+                return Err(abandon(ErrorKind::Clobbered.wrap(error), false));
+            }
+        }
+
+        span.exit();
+
+        let pathspecs = snapshot::pathspecs(&self.include, &self.exclude);
+        let changed = before.changed_matching(&after, &pathspecs)?;
+        let left_out = touched
+            .difference(&changed)
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        if changed.is_empty() {
+            let error = anyhow!(
+                "none of the paths the command changed are included by --include and --exclude"
+            );
+            return Err(abandon(ErrorKind::NothingIncluded.wrap(error), false));
+        }
+        if !left_out.is_empty() {
+            info!(
+                ?left_out,
+                "not committing changed paths which aren't included"
+            );
+        }
+
+        info_span!("stage", paths = changed.len())
+            .in_scope(|| backend::open(&root)?.stage(&changed))?;
+        Ok(RunOutcome {
+            spec: spec.clone(),
+            changed,
+            left_out,
+            exit_code: exit_code.unwrap_or_default(),
+            signal,
+            transcript,
+            duration,
+            root,
+            repository,
+            rollback,
+            allow_dirty: self.allow_dirty,
+            branch: self.branch.clone(),
+            stdout_to_stderr: self.stdout_to_stderr,
+            stdin: blob,
+            pathspecs,
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+            before,
+            after,
+            worktree,
+        })
+    }
+}
+
+/// Paths by how they changed, relative to the top of the repository.
+#[derive(Debug, Default)]
+pub struct Files {
+    pub added: BTreeSet<PathBuf>,
+    pub modified: BTreeSet<PathBuf>,
+    pub deleted: BTreeSet<PathBuf>,
+}
+
+/// A command which has run, with its changes staged, ready to commit.
+pub struct RunOutcome {
+    pub(crate) spec: RunSpec,
+    /// The paths to commit, relative to the top of the repository.
+    pub changed: BTreeSet<PathBuf>,
+    /// Paths which the command changed, but which aren't included.
+    pub left_out: BTreeSet<PathBuf>,
+    pub exit_code: i32,
+    /// The signal which interrupted the command, with [`Runner::allow_signal`].
+    pub signal: Option<i32>,
+    pub duration: Duration,
+    root: PathBuf,
+    /// The top of the repository, which [`Self::root`] is a worktree of with [`Runner::worktree`].
+    repository: PathBuf,
+    rollback: bool,
+    allow_dirty: bool,
+    branch: Option<String>,
+    stdout_to_stderr: bool,
+    /// Stdout and stderr, interleaved, if they were captured.
+    transcript: Option<NamedTempFile>,
+    /// The blob holding the command's input.
+    stdin: Option<String>,
+    pathspecs: Vec<OsString>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    before: Snapshot,
+    after: Snapshot,
+    pub(crate) worktree: Option<Worktree>,
+}
+
+impl RunOutcome {
+    /// Stdout and stderr, interleaved, if they were captured.
+    pub fn output(&self) -> anyhow::Result<Option<Vec<u8>>> {
+        match &self.transcript {
+            Some(transcript) => Ok(Some(fs::read(transcript.path())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Where the changes are, which is a temporary worktree with [`Runner::worktree`].
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// `git` at [`Self::root`].
+    pub(crate) fn git(&self) -> Command {
+        let mut git = git();
+        git.current_dir(&self.root);
+        git
+    }
+
+    /// [`Self::changed`], by how they changed.
+    pub fn files(&self) -> anyhow::Result<Files> {
+        let added = self.before.added(&self.after)?;
+        // in `before` but not `after`
+        let deleted = self.after.added(&self.before)?;
+        let mut files = Files::default();
+        for path in &self.changed {
+            let files = match (added.contains(path), deleted.contains(path)) {
+                (true, _) => &mut files.added,
+                (_, true) => &mut files.deleted,
+                _ => &mut files.modified,
+            };
+            files.insert(path.clone());
+        }
+        Ok(files)
+    }
+
+    /// Arguments for `git diff` which show the changes to commit.
+    pub(crate) fn diff(&self) -> Vec<OsString> {
+        match self.allow_dirty {
+            // the index may have other changes, so compare the snapshots
+            true => [&self.before.tree, &self.after.tree, "--"]
+                .into_iter()
+                .map(OsString::from)
+                .chain(self.pathspecs.iter().cloned())
+                .collect(),
+            false => vec![OsString::from("--cached")],
+        }
+    }
+
+    /// The directory the command ran in, relative to the top of the repository.
+    pub fn cwd(&self) -> String {
+        match self.spec.cwd.as_os_str().is_empty() {
+            true => String::from("."),
+            false => self.spec.cwd.display().to_string(),
+        }
+    }
+
+    /// How long the command took, as recorded in the message.
+    pub(crate) fn seconds(&self) -> String {
+        format!("{:.3}s", self.duration.as_secs_f64())
+    }
+
+    /// The default commit message, with trailers recording the command.
+    pub fn message(&self) -> Message {
+        let mut message = self.spec.invocation.message();
+        let capture = self.transcript.is_some();
+        if capture || self.exit_code != 0 {
+            message = message.trailer(RUN_EXIT_CODE, self.exit_code.to_string());
+        }
+        if capture {
+            message = message.trailer(RUN_DURATION, self.seconds());
+        }
+        if !self.spec.cwd.as_os_str().is_empty() {
+            message = message.trailer(RUN_CWD, self.cwd());
+        }
+        if !self.include.is_empty() {
+            message = message.trailer(RUN_INCLUDE, encode(&self.include));
+        }
+        if !self.exclude.is_empty() {
+            message = message.trailer(RUN_EXCLUDE, encode(&self.exclude));
+        }
+        if let Some(blob) = &self.stdin {
+            message = message.trailer(RUN_STDIN, blob);
+        }
+        if let Some(signal) = self.signal {
+            message = message.trailer(RUN_SIGNAL, signals::name(signal));
+        }
+        self.spec.env.record(message)
+    }
+
+    /// Put the paths the command changed back how they were.
+    ///
+    /// Does nothing with [`Runner::worktree`].
+    pub fn rollback(&self) -> anyhow::Result<()> {
+        match self.worktree {
+            Some(_) => Ok(()),
+            None => self.before.rollback(true).map(drop),
+        }
+    }
+
+    /// Give up with `error`, rolling back if the [`Runner`] says to.
+    pub(crate) fn abandon(&self, error: anyhow::Error) -> anyhow::Error {
+        abandon(&self.before, self.rollback, error, true)
+    }
+
+    /// Commit [`Self::changed`] with `message`, returning the new commit.
+    pub fn commit(&self, message: &str) -> anyhow::Result<String> {
+        self.commit_paths(&self.changed, message)
+    }
+
+    /// Commit `paths`, which should be some of [`Self::changed`], with `message`.
+    pub fn commit_paths(&self, paths: &BTreeSet<PathBuf>, message: &str) -> anyhow::Result<String> {
+        // commit only the paths we staged, even if others have staged changes
+        let pathspecs = snapshot::pathspec_file(paths)?;
+        let mut commit = snapshot::git_paths(&self.root, &["commit"], &pathspecs);
+        commit.args(["--message", message]);
+        self.finish(commit)
+    }
+
+    /// Commit whatever is staged with `message`, for when only some hunks of [`Self::changed`] are.
+    pub fn commit_index(&self, message: &str) -> anyhow::Result<String> {
+        let mut commit = self.git();
+        commit.args(["commit", "--message", message]);
+        self.finish(commit)
+    }
+
+    fn visible<'a>(&self, command: &'a mut Command) -> &'a mut Command {
+        shown(command, self.stdout_to_stderr)
+    }
+
+    fn finish(&self, mut commit: Command) -> anyhow::Result<String> {
+        let _span = info_span!("commit").entered();
+        errexit(run(self.visible(&mut commit))?)?;
+
+        if let Some(transcript) = &self.transcript {
+            if transcript.as_file().metadata()?.len() > 0 {
+                errexit(run(self.visible(
+                    self.git()
+                        .args(["notes", "--ref", NOTES_REF, "add", "--file"])
+                        .arg(transcript.path())
+                        .arg("HEAD"),
+                ))?)?;
+            }
+        }
+
+        if let Some(blob) = &self.stdin {
+            errexit(run(self.visible(self.git().args([
+                "update-ref",
+                &format!("{STDIN_REFS}{blob}"),
+                blob,
+            ])))?)?;
+        }
+
+        let commit = read(self.git().args(["rev-parse", "HEAD"]))?;
+        let commit = commit.trim();
+        if self.worktree.is_some() {
+            match &self.branch {
+                Some(branch) => errexit(run(self.visible(
+                    git().current_dir(&self.repository).args(["branch", "--end-of-options", branch, commit]),
+                ))?)?,
+                None => errexit(run(self.visible(
+                    git().current_dir(&self.repository).args(["merge", "--ff-only", "--quiet", commit]),
+                ))?)
+                .with_context(|| format!("couldn't fast-forward to {commit}, use `git merge {commit}` or `git branch <name> {commit}` to keep it"))?,
+            };
+        }
+        info!(commit, "committed");
+        Ok(commit.into())
+    }
+}
+
+/// Give up with `error`, first rolling back to `before` if asked to.
+fn abandon(before: &Snapshot, rollback: bool, error: anyhow::Error, staged: bool) -> anyhow::Error {
+    if !rollback {
+        return error;
+    }
+    let _span = info_span!("rollback", staged).entered();
+    match before.rollback(staged) {
+        Ok(false) => error,
+        Ok(true) => {
+            info!("rolled back the changes made by the command");
+            Failure::rolled_back(error)
+        }
+        Err(e) => error.context(format!(
+            "couldn't roll back the changes made by the command: {e:#}"
+        )),
+    }
+}
+
+/// Like [`visible`], but maybe with stdout going to stderr.
+pub(crate) fn shown(command: &mut Command, stdout_to_stderr: bool) -> &mut Command {
+    visible(command);
+    if stdout_to_stderr {
+        command.stdout(io::stderr());
+    }
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Repository;
+
+    fn sh(script: &str) -> RunSpec {
+        RunSpec::argv(["sh", "-c", script]).unwrap()
+    }
+
+    fn fail(runner: Runner, repository: &Repository, script: &str) -> (ErrorKind, bool) {
+        let error = runner
+            .repository(repository.path())
+            .run(&sh(script))
+            .err()
+            .expect("the run failed");
+        let failure = error.downcast_ref::<Failure>().expect("it's a Failure");
+        (failure.kind, failure.rolled_back)
+    }
+
+    #[test]
+    fn rolls_back_new_files_and_directories() {
+        let repository = Repository::new();
+        let (kind, rolled_back) = fail(
+            Runner::new(),
+            &repository,
+            "mkdir -p new/deeper && echo new > new/deeper/file && echo new > top; exit 1",
+        );
+        assert_eq!((kind, rolled_back), (ErrorKind::CommandFailed, true));
+        assert!(!repository.path().join("new").exists());
+        assert_eq!(repository.read("top"), None);
+        assert_eq!(repository.git(&["status", "--porcelain"]), "");
+    }
+
+    #[test]
+    fn rolls_back_modified_and_deleted_files() {
+        let repository = Repository::new();
+        repository.write("modified", "before\n");
+        repository.write("deleted", "deleted\n");
+        repository.commit("files");
+        let (kind, rolled_back) = fail(
+            Runner::new(),
+            &repository,
+            "echo after > modified && rm deleted; exit 1",
+        );
+        assert_eq!((kind, rolled_back), (ErrorKind::CommandFailed, true));
+        assert_eq!(repository.read("modified").as_deref(), Some("before\n"));
+        assert_eq!(repository.read("deleted").as_deref(), Some("deleted\n"));
+        assert_eq!(repository.git(&["status", "--porcelain"]), "");
+    }
+
+    #[test]
+    fn keeps_what_was_already_dirty() {
+        let repository = Repository::new();
+        repository.write("dirty", "committed\n");
+        repository.commit("files");
+        repository.write("dirty", "dirty\n");
+        repository.write("untracked", "untracked\n");
+
+        let (kind, rolled_back) = fail(
+            Runner::new().allow_dirty(true),
+            &repository,
+            "echo new > new; exit 1",
+        );
+        assert_eq!((kind, rolled_back), (ErrorKind::CommandFailed, true));
+        assert_eq!(repository.read("new"), None);
+        assert_eq!(repository.read("dirty").as_deref(), Some("dirty\n"));
+        assert_eq!(repository.read("untracked").as_deref(), Some("untracked\n"));
+
+        // a command which changes them can't be committed, and they're put back as they were
+        let (kind, rolled_back) = fail(
+            Runner::new().allow_dirty(true),
+            &repository,
+            "echo clobbered > dirty && echo clobbered > untracked",
+        );
+        assert_eq!((kind, rolled_back), (ErrorKind::Clobbered, true));
+        assert_eq!(repository.read("dirty").as_deref(), Some("dirty\n"));
+        assert_eq!(repository.read("untracked").as_deref(), Some("untracked\n"));
+    }
+
+    #[test]
+    fn no_changes_to_roll_back() {
+        let repository = Repository::new();
+        let (kind, rolled_back) = fail(Runner::new(), &repository, "exit 1");
+        assert_eq!((kind, rolled_back), (ErrorKind::CommandFailed, false));
+        let (kind, rolled_back) = fail(Runner::new(), &repository, "true");
+        assert_eq!((kind, rolled_back), (ErrorKind::NoChanges, false));
+    }
+
+    #[test]
+    fn without_rollback() {
+        let repository = Repository::new();
+        let (kind, rolled_back) = fail(
+            Runner::new().rollback(false),
+            &repository,
+            "echo kept > kept; exit 1",
+        );
+        assert_eq!((kind, rolled_back), (ErrorKind::CommandFailed, false));
+        assert_eq!(repository.read("kept").as_deref(), Some("kept\n"));
+    }
+}
@@ -0,0 +1,57 @@
+//! The shells which `--shell` knows how to pass a script to.
+
+use std::{ffi::OsStr, fmt, path::Path, process::Command};
+
+/// A shell program, as named by the user, e.g `zsh` or `/usr/bin/fish`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shell(String);
+
+impl Shell {
+    pub fn new(program: impl Into<String>) -> Self {
+        Self(program.into())
+    }
+
+    pub fn bash() -> Self {
+        Self::new("bash")
+    }
+
+    pub fn program(&self) -> &str {
+        &self.0
+    }
+
+    /// The name of the shell, without any directory, like `zsh` for `/usr/bin/zsh`.
+    pub fn name(&self) -> String {
+        Path::new(&self.0)
+            .file_name()
+            .map(|it| it.to_string_lossy().into_owned())
+            .unwrap_or_else(|| self.0.clone())
+    }
+
+    /// A command which runs `script` with this shell.
+    pub fn command(&self, script: &OsStr) -> Command {
+        let mut command = Command::new(&self.0);
+        command.args(self.flags()).arg(script);
+        command
+    }
+
+    /// The flags which precede the script.
+    fn flags(&self) -> &'static [&'static str] {
+        let name = Path::new(&self.0)
+            .file_stem()
+            .and_then(OsStr::to_str)
+            .unwrap_or_default();
+        match name {
+            "bash" => &["-O", "globstar", "-c"],
+            "pwsh" | "powershell" => &["-NoProfile", "-NonInteractive", "-Command"],
+            "cmd" => &["/C"],
+            // sh, dash, ksh, zsh, fish and nu all take `-c`
+            _ => &["-c"],
+        }
+    }
+}
+
+impl fmt::Display for Shell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
@@ -0,0 +1,238 @@
+//! Passing SIGINT and SIGTERM on to the command while it runs, so that git-run outlives it
+//! and can put things right afterwards.
+//!
+//! Unless it's interactive, the command runs in its own process group, and the whole group is
+//! sent the signal.
+//! If we're in the foreground of a terminal, that group is put in the foreground instead while it
+//! runs, so that it may still use the terminal.
+//! An interactive command shares our process group, so that it may read from the terminal,
+//! and already gets SIGINT from the terminal itself, so only SIGTERM is passed on.
+//!
+//! Signal handlers and the terminal belong to the whole process, so only one [`Forward`] may exist
+//! at a time, and others wait for it to be dropped.
+
+use std::process::{Child, Command, ExitStatus};
+
+/// Catches SIGINT and SIGTERM until dropped.
+pub struct Forward {
+    own_group: bool,
+    #[cfg(unix)]
+    previous: Vec<(libc::c_int, libc::sigaction)>,
+    /// Our controlling terminal, if we're in its foreground and the command runs in its own group.
+    #[cfg(unix)]
+    terminal: Option<std::fs::File>,
+    #[cfg(unix)]
+    _turn: std::sync::MutexGuard<'static, ()>,
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::sync::{
+        atomic::{AtomicBool, AtomicI32, Ordering},
+        Mutex,
+    };
+
+    /// Held by the [`super::Forward`] which is installed.
+    pub static TURN: Mutex<()> = Mutex::new(());
+
+    /// The command being run, or 0.
+    pub static CHILD: AtomicI32 = AtomicI32::new(0);
+    pub static OWN_GROUP: AtomicBool = AtomicBool::new(false);
+    /// The last signal caught, or 0.
+    pub static RECEIVED: AtomicI32 = AtomicI32::new(0);
+
+    pub extern "C" fn handle(signal: libc::c_int) {
+        RECEIVED.store(signal, Ordering::SeqCst);
+        forward(signal);
+    }
+
+    pub fn forward(signal: libc::c_int) {
+        let child = CHILD.load(Ordering::SeqCst);
+        if child <= 0 {
+            return;
+        }
+        // kill is async-signal-safe
+        unsafe {
+            match OWN_GROUP.load(Ordering::SeqCst) {
+                true => libc::kill(-child, signal),
+                false if signal == libc::SIGTERM => libc::kill(child, signal),
+                false => 0,
+            };
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Forward {
+    /// Start catching signals, for a command which will run in its own process group if `own_group`.
+    ///
+    /// Waits for any other [`Forward`] to be dropped first.
+    pub fn install(own_group: bool) -> std::io::Result<Self> {
+        use std::sync::atomic::Ordering;
+        let turn = imp::TURN
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        imp::RECEIVED.store(0, Ordering::SeqCst);
+        imp::OWN_GROUP.store(own_group, Ordering::SeqCst);
+        let mut forward = Self {
+            own_group,
+            previous: vec![],
+            terminal: match own_group {
+                true => foreground_terminal(),
+                false => None,
+            },
+            _turn: turn,
+        };
+        for signal in [libc::SIGINT, libc::SIGTERM] {
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction =
+                    imp::handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+                action.sa_flags = libc::SA_RESTART;
+                libc::sigemptyset(&mut action.sa_mask);
+                let mut previous: libc::sigaction = std::mem::zeroed();
+                if libc::sigaction(signal, &action, &mut previous) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                forward.previous.push((signal, previous));
+            }
+        }
+        Ok(forward)
+    }
+
+    /// Set up `command` to run in its own process group, if it should.
+    pub fn prepare(&self, command: &mut Command) {
+        if self.own_group {
+            std::os::unix::process::CommandExt::process_group(command, 0);
+        }
+    }
+
+    /// Pass signals on to `child`, including any caught since [`Self::install`],
+    /// and give it the terminal if we have it.
+    pub fn to(&self, child: &Child) {
+        use std::{os::fd::AsRawFd as _, sync::atomic::Ordering};
+        let pid = i32::try_from(child.id()).expect("pids fit in an i32");
+        imp::CHILD.store(pid, Ordering::SeqCst);
+        if let Some(terminal) = &self.terminal {
+            unsafe {
+                libc::tcsetpgrp(terminal.as_raw_fd(), pid);
+                // in case it tried to use the terminal before it had it, and was stopped
+                libc::kill(-pid, libc::SIGCONT);
+            }
+        }
+        match imp::RECEIVED.load(Ordering::SeqCst) {
+            0 => {}
+            signal => imp::forward(signal),
+        }
+    }
+
+    /// The last signal caught, if any.
+    pub fn received(&self) -> Option<i32> {
+        match imp::RECEIVED.load(std::sync::atomic::Ordering::SeqCst) {
+            0 => None,
+            signal => Some(signal),
+        }
+    }
+
+    /// Kill `child`, and with it the rest of its process group if it has its own.
+    pub fn kill(&self, child: &mut Child) -> std::io::Result<()> {
+        if !self.own_group {
+            return child.kill();
+        }
+        let pid = i32::try_from(child.id()).expect("pids fit in an i32");
+        match unsafe { libc::kill(-pid, libc::SIGKILL) } {
+            0 => Ok(()),
+            _ => Err(std::io::Error::last_os_error()),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Forward {
+    fn drop(&mut self) {
+        use std::os::fd::AsRawFd as _;
+        imp::CHILD.store(0, std::sync::atomic::Ordering::SeqCst);
+        if let Some(terminal) = &self.terminal {
+            // we're in the background until we have the terminal back, which would stop us
+            unsafe {
+                let mut ignore: libc::sigaction = std::mem::zeroed();
+                ignore.sa_sigaction = libc::SIG_IGN;
+                libc::sigemptyset(&mut ignore.sa_mask);
+                let mut previous: libc::sigaction = std::mem::zeroed();
+                libc::sigaction(libc::SIGTTOU, &ignore, &mut previous);
+                libc::tcsetpgrp(terminal.as_raw_fd(), libc::getpgrp());
+                libc::sigaction(libc::SIGTTOU, &previous, std::ptr::null_mut());
+            }
+        }
+        for (signal, previous) in &self.previous {
+            unsafe {
+                libc::sigaction(*signal, previous, std::ptr::null_mut());
+            }
+        }
+    }
+}
+
+/// Our controlling terminal, if we're in its foreground.
+#[cfg(unix)]
+fn foreground_terminal() -> Option<std::fs::File> {
+    use std::os::fd::AsRawFd as _;
+    let terminal = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .ok()?;
+    match unsafe { libc::tcgetpgrp(terminal.as_raw_fd()) == libc::getpgrp() } {
+        true => Some(terminal),
+        false => None,
+    }
+}
+
+#[cfg(not(unix))]
+impl Forward {
+    pub fn install(own_group: bool) -> std::io::Result<Self> {
+        Ok(Self { own_group })
+    }
+
+    pub fn prepare(&self, _: &mut Command) {}
+
+    pub fn to(&self, _: &Child) {}
+
+    pub fn received(&self) -> Option<i32> {
+        None
+    }
+
+    pub fn kill(&self, child: &mut Child) -> std::io::Result<()> {
+        child.kill()
+    }
+}
+
+/// The signal which killed a process with `status`, if it was killed by one.
+pub fn of(status: &ExitStatus) -> Option<i32> {
+    #[cfg(unix)]
+    return std::os::unix::process::ExitStatusExt::signal(status);
+    #[cfg(not(unix))]
+    return None;
+}
+
+/// The name of `signal`, such as `SIGINT`.
+pub fn name(signal: i32) -> String {
+    #[cfg(unix)]
+    let name = match signal {
+        libc::SIGHUP => Some("SIGHUP"),
+        libc::SIGINT => Some("SIGINT"),
+        libc::SIGQUIT => Some("SIGQUIT"),
+        libc::SIGABRT => Some("SIGABRT"),
+        libc::SIGKILL => Some("SIGKILL"),
+        libc::SIGSEGV => Some("SIGSEGV"),
+        libc::SIGPIPE => Some("SIGPIPE"),
+        libc::SIGALRM => Some("SIGALRM"),
+        libc::SIGTERM => Some("SIGTERM"),
+        _ => None,
+    };
+    #[cfg(not(unix))]
+    let name = None;
+    match name {
+        Some(name) => name.into(),
+        None => format!("signal {signal}"),
+    }
+}
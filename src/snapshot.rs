@@ -0,0 +1,195 @@
+//! Recording the state of the worktree without touching the index,
+//! so that we can tell which paths a command changed.
+
+use crate::{
+    backend, errexit, git,
+    invocation::{from_bytes, to_bytes},
+    run, visible,
+};
+use anyhow::Context as _;
+use std::{
+    collections::BTreeSet,
+    ffi::OsString,
+    fs,
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+use tempfile::NamedTempFile;
+
+/// A tree object holding the contents of the worktree at `root`, including untracked files.
+pub struct Snapshot {
+    pub root: PathBuf,
+    pub tree: String,
+}
+
+impl Snapshot {
+    /// The tree at HEAD, which is the state of a clean worktree.
+    pub fn head(root: &Path) -> anyhow::Result<Self> {
+        Ok(Self {
+            root: root.into(),
+            tree: backend::open(root)?.head_tree()?,
+        })
+    }
+
+    pub fn take(root: &Path) -> anyhow::Result<Self> {
+        Ok(Self {
+            root: root.into(),
+            tree: backend::open(root)?.worktree_tree()?,
+        })
+    }
+
+    /// Paths which differ between `self` and `other`, relative to the top of the repository.
+    pub fn changed(&self, other: &Snapshot) -> anyhow::Result<BTreeSet<PathBuf>> {
+        self.changed_matching(other, &[])
+    }
+
+    /// Like [`Self::changed`], but only paths matching `pathspecs`, which are relative to the top of the repository.
+    pub fn changed_matching(
+        &self,
+        other: &Snapshot,
+        pathspecs: &[OsString],
+    ) -> anyhow::Result<BTreeSet<PathBuf>> {
+        backend::open(&self.root)?.diff_trees(&self.tree, &other.tree, false, pathspecs)
+    }
+
+    /// Paths which are in `other` but not in `self`.
+    pub fn added(&self, other: &Snapshot) -> anyhow::Result<BTreeSet<PathBuf>> {
+        backend::open(&self.root)?.diff_trees(&self.tree, &other.tree, true, &[])
+    }
+
+    /// Put the paths which have changed since `self` was taken back how they were.
+    ///
+    /// If the changes have been `staged`, their index entries are reset to HEAD.
+    /// Returns whether anything had changed.
+    pub fn rollback(&self, staged: bool) -> anyhow::Result<bool> {
+        let now = Snapshot::take(&self.root)?;
+        let changed = self.changed(&now)?;
+        if changed.is_empty() {
+            return Ok(false);
+        }
+        let added = self.added(&now)?;
+
+        if staged {
+            let pathspecs = pathspec_file(&changed)?;
+            errexit(run(visible(&mut git_paths(
+                &self.root,
+                &["reset", "--quiet"],
+                &pathspecs,
+            )))?)?;
+        }
+
+        let restore = changed.difference(&added).collect::<Vec<_>>();
+        if !restore.is_empty() {
+            let pathspecs = pathspec_file(restore)?;
+            errexit(run(visible(&mut git_paths(
+                &self.root,
+                &["restore", "--worktree", "--source", &self.tree],
+                &pathspecs,
+            )))?)?;
+        }
+
+        for path in &added {
+            let path = self.root.join(path);
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(e).with_context(|| format!("couldn't remove {}", path.display()))
+                }
+                _ => {}
+            }
+            // tidy up directories the command created, stopping at the first non-empty one
+            for dir in path.ancestors().skip(1) {
+                if dir == self.root || fs::remove_dir(dir).is_err() {
+                    break;
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Paths which differ from HEAD in the index or in `self`.
+    pub fn dirty(&self) -> anyhow::Result<BTreeSet<PathBuf>> {
+        let backend = backend::open(&self.root)?;
+        let mut dirty = backend.diff_trees(&backend.head_tree()?, &self.tree, false, &[])?;
+        dirty.extend(backend.staged()?);
+        Ok(dirty)
+    }
+}
+
+/// Pathspecs which select paths matching one of `include`, or any path if it's empty,
+/// and not matching any of `exclude`.
+pub fn pathspecs(include: &[String], exclude: &[String]) -> Vec<OsString> {
+    include
+        .iter()
+        .map(OsString::from)
+        .chain(exclude.iter().map(|it| format!(":(exclude){it}").into()))
+        .collect()
+}
+
+pub fn paths(command: &mut Command) -> anyhow::Result<BTreeSet<PathBuf>> {
+    let output = errexit(run(command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit()))?)?;
+    output
+        .stdout
+        .split(|it| *it == b'\0')
+        .filter(|it| !it.is_empty())
+        .map(|it| from_bytes(it.to_vec()).map(PathBuf::from))
+        .collect()
+}
+
+/// `git SUBCOMMAND`, run at `root` and limited to the paths in `pathspecs`.
+pub fn git_paths(root: &Path, subcommand: &[&str], pathspecs: &NamedTempFile) -> Command {
+    let mut git = git_in(root);
+    git.arg("--literal-pathspecs")
+        .args(subcommand)
+        .arg("--pathspec-file-nul")
+        .arg("--pathspec-from-file")
+        .arg(pathspecs.path());
+    git
+}
+
+fn git_in(root: &Path) -> Command {
+    let mut git = git();
+    git.current_dir(root);
+    git
+}
+
+/// A file for `--pathspec-from-file` which matches exactly `paths`,
+/// for use with `--literal-pathspecs` and `--pathspec-file-nul`.
+pub fn pathspec_file<'a>(
+    paths: impl IntoIterator<Item = &'a PathBuf>,
+) -> anyhow::Result<NamedTempFile> {
+    let mut file = NamedTempFile::new()?;
+    for path in paths {
+        file.write_all(&to_bytes(path.as_os_str()))?;
+        file.write_all(b"\0")?;
+    }
+    file.flush()?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Repository;
+
+    #[test]
+    fn rollback_unstages() {
+        let repository = Repository::new();
+        repository.write("modified", "before\n");
+        repository.commit("files");
+        let before = Snapshot::head(repository.path()).unwrap();
+
+        repository.write("modified", "after\n");
+        repository.write("added", "added\n");
+        repository.git(&["add", "--all"]);
+        assert!(before.rollback(true).unwrap());
+        assert_eq!(repository.read("modified").as_deref(), Some("before\n"));
+        assert_eq!(repository.read("added"), None);
+        assert_eq!(repository.git(&["status", "--porcelain"]), "");
+
+        assert!(!before.rollback(true).unwrap());
+    }
+}
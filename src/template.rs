@@ -0,0 +1,179 @@
+//! Commit message templates, like `chore(fmt): {command}`.
+//!
+//! The first line of the rendered template is the subject, and the rest is the body.
+//! Literal braces are written `{{` and `}}`.
+
+use anyhow::bail;
+use std::{fmt, str::FromStr};
+
+pub const DEFAULT: &str = "run: {command}";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placeholder {
+    /// The command, shell-quoted.
+    Command,
+    /// The name of the program that was run.
+    Program,
+    ExitCode,
+    /// How long the command took, e.g `1.234s`.
+    Duration,
+    /// The paths which will be committed, separated by `, `.
+    ChangedFiles,
+    /// The directory the command was run in, relative to the top of the repository.
+    Cwd,
+}
+
+impl Placeholder {
+    const ALL: &'static [(&'static str, Placeholder)] = &[
+        ("command", Placeholder::Command),
+        ("program", Placeholder::Program),
+        ("exit_code", Placeholder::ExitCode),
+        ("duration", Placeholder::Duration),
+        ("changed_files", Placeholder::ChangedFiles),
+        ("cwd", Placeholder::Cwd),
+    ];
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    /// Fill in the placeholders, returning the subject and the body.
+    pub fn render(&self, mut value: impl FnMut(Placeholder) -> String) -> (String, String) {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(s) => rendered.push_str(s),
+                Segment::Placeholder(it) => rendered.push_str(&value(*it)),
+            }
+        }
+        match rendered.split_once('\n') {
+            Some((subject, body)) => (subject.trim_end().into(), body.trim().into()),
+            None => (rendered, String::new()),
+        }
+    }
+}
+
+impl FromStr for Template {
+    type Err = anyhow::Error;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut segments = vec![];
+        let mut literal = String::new();
+        let mut chars = source.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let Some((name, rest)) = chars.as_str().split_once('}') else {
+                        bail!("unclosed `{{` in message template {source:?}")
+                    };
+                    let Some((_, placeholder)) =
+                        Placeholder::ALL.iter().find(|(it, _)| *it == name)
+                    else {
+                        bail!(
+                            "unknown placeholder `{{{name}}}` in message template {source:?}, expected one of {}",
+                            Placeholder::ALL
+                                .iter()
+                                .map(|(it, _)| format!("`{{{it}}}`"))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        )
+                    };
+                    segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    segments.push(Segment::Placeholder(*placeholder));
+                    chars = rest.chars();
+                }
+                '}' => bail!(
+                    "unmatched `}}` in message template {source:?}, use `}}}}` for a literal brace"
+                ),
+                c => literal.push(c),
+            }
+        }
+        segments.push(Segment::Literal(literal));
+        if source.trim().is_empty() {
+            bail!("message template is empty")
+        }
+        Ok(Self {
+            source: source.into(),
+            segments,
+        })
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str) -> (String, String) {
+        template
+            .parse::<Template>()
+            .unwrap()
+            .render(|placeholder| match placeholder {
+                Placeholder::Command => "cargo fmt".into(),
+                Placeholder::Program => "cargo".into(),
+                other => format!("<{other:?}>"),
+            })
+    }
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(
+            render("chore({program}): {command} in {cwd}"),
+            ("chore(cargo): cargo fmt in <Cwd>".into(), String::new())
+        );
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(
+            render("{{command}} is {{{command}}}"),
+            ("{command} is {cargo fmt}".into(), String::new())
+        );
+    }
+
+    #[test]
+    fn subject_and_body() {
+        assert_eq!(
+            render("run: {command}  \n\n  took {duration}\n\n"),
+            ("run: cargo fmt".into(), "took <Duration>".into())
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let error = |template: &str| template.parse::<Template>().unwrap_err().to_string();
+        assert!(error("run: {cmd}").contains("unknown placeholder `{cmd}`"));
+        assert!(error("run: {command").contains("unclosed `{`"));
+        assert!(error("run: command}").contains("unmatched `}`"));
+        assert!(error("  ").contains("empty"));
+    }
+
+    #[test]
+    fn display_is_the_source() {
+        let source = "fmt: {{{command}}}";
+        assert_eq!(source.parse::<Template>().unwrap().to_string(), source);
+    }
+}
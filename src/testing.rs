@@ -0,0 +1,63 @@
+//! Temporary repositories for tests.
+
+use crate::git;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use tempfile::TempDir;
+
+/// A repository in a temporary directory, removed on drop.
+pub struct Repository {
+    path: PathBuf,
+    _dir: TempDir,
+}
+
+impl Repository {
+    /// A new repository, with an empty first commit.
+    pub fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        // as `git rev-parse --show-toplevel` would have it
+        let path = dir.path().canonicalize().unwrap();
+        let repository = Self { path, _dir: dir };
+        repository.git(&["init", "--quiet"]);
+        for (key, value) in [
+            ("user.name", "Test"),
+            ("user.email", "test@example.com"),
+            ("commit.gpgsign", "false"),
+        ] {
+            repository.git(&["config", key, value]);
+        }
+        repository.git(&["commit", "--quiet", "--allow-empty", "--message", "root"]);
+        repository
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Run `git` with `args` in the repository, returning what it prints.
+    pub fn git(&self, args: &[&str]) -> String {
+        let output = git().current_dir(&self.path).args(args).output().unwrap();
+        assert!(output.status.success(), "git {args:?} failed: {output:?}");
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    /// Write `contents` to `path`, creating the directories it's in.
+    pub fn write(&self, path: &str, contents: &str) {
+        let path = self.path.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    /// The contents of `path`, if it exists.
+    pub fn read(&self, path: &str) -> Option<String> {
+        fs::read_to_string(self.path.join(path)).ok()
+    }
+
+    /// Stage everything and commit it.
+    pub fn commit(&self, message: &str) {
+        self.git(&["add", "--all"]);
+        self.git(&["commit", "--quiet", "--message", message]);
+    }
+}
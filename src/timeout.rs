@@ -0,0 +1,103 @@
+//! How long a command may run for, like `90s`, `5m` or `1.5h`.
+//!
+//! A number without a unit is in seconds, and `0` or `none` means there's no limit.
+
+use anyhow::{bail, Context as _};
+use std::{fmt, str::FromStr, time::Duration};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Timeout(Option<Duration>);
+
+impl Timeout {
+    /// How long the command may run for, if there is a limit.
+    pub fn duration(self) -> Option<Duration> {
+        self.0
+    }
+}
+
+impl From<Option<Duration>> for Timeout {
+    fn from(duration: Option<Duration>) -> Self {
+        Self(duration.filter(|it| !it.is_zero()))
+    }
+}
+
+impl FromStr for Timeout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s == "none" {
+            return Ok(Self(None));
+        }
+        let split = s
+            .find(|it: char| !(it.is_ascii_digit() || it == '.'))
+            .unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let number = number.parse::<f64>().with_context(|| {
+            format!("invalid timeout {s:?}, expected a number and a unit like `90s` or `5m`")
+        })?;
+        let seconds = match unit {
+            "ms" => number / 1000.0,
+            "" | "s" => number,
+            "m" => number * 60.0,
+            "h" => number * 60.0 * 60.0,
+            _ => bail!(
+                "unknown unit {unit:?} in timeout {s:?}, expected one of `ms`, `s`, `m` or `h`"
+            ),
+        };
+        let duration = Duration::try_from_secs_f64(seconds)
+            .with_context(|| format!("invalid timeout {s:?}"))?;
+        match duration.is_zero() {
+            true => Ok(Self(None)),
+            false => Ok(Self(Some(duration))),
+        }
+    }
+}
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(duration) => write!(f, "{}s", duration.as_secs_f64()),
+            None => f.write_str("none"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Option<Duration> {
+        s.parse::<Timeout>().unwrap().duration()
+    }
+
+    #[test]
+    fn units() {
+        assert_eq!(parse("250ms"), Some(Duration::from_millis(250)));
+        assert_eq!(parse("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse("5m"), Some(Duration::from_secs(5 * 60)));
+        assert_eq!(parse("1.5h"), Some(Duration::from_secs(90 * 60)));
+    }
+
+    #[test]
+    fn bare_number_is_seconds() {
+        assert_eq!(parse("30"), Some(Duration::from_secs(30)));
+        assert_eq!(parse(" 2.5 "), Some(Duration::from_millis(2500)));
+    }
+
+    #[test]
+    fn no_limit() {
+        assert_eq!(parse("0"), None);
+        assert_eq!(parse("0s"), None);
+        assert_eq!(parse("none"), None);
+        assert_eq!(Timeout::from(Some(Duration::ZERO)), Timeout::default());
+        assert_eq!(Timeout::default().to_string(), "none");
+    }
+
+    #[test]
+    fn malformed() {
+        for s in ["", "s", "5 m", "5d", "1.2.3s", "-1s", "soon", "none5"] {
+            assert!(s.parse::<Timeout>().is_err(), "{s:?} parsed");
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use crate::{errexit, git, run};
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+use tempfile::TempDir;
+
+/// A detached `git worktree`, removed on drop.
+pub struct Worktree {
+    path: PathBuf,
+    /// The repository the worktree belongs to.
+    repository: PathBuf,
+    // dropped after `path` has been unregistered
+    _scratch: TempDir,
+}
+
+impl Worktree {
+    /// Check out `commit` in a new worktree of the repository containing `repository`.
+    pub fn add(repository: &Path, commit: &str) -> anyhow::Result<Self> {
+        let scratch = tempfile::tempdir()?;
+        let path = scratch.path().join("worktree");
+        errexit(run(git()
+            .current_dir(repository)
+            .args(["worktree", "add", "--detach", "--quiet"])
+            .arg(&path)
+            .arg(commit)
+            .stdout(Stdio::null())
+            .stderr(Stdio::inherit()))?)?;
+        Ok(Self {
+            path,
+            repository: repository.to_owned(),
+            _scratch: scratch,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Worktree {
+    fn drop(&mut self) {
+        let _ = git()
+            .current_dir(&self.repository)
+            .args(["worktree", "remove", "--force"])
+            .arg(&self.path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+    }
+}